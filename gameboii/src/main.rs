@@ -8,15 +8,36 @@ extern crate piston;
 
 mod window;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use libgameboii::cheats::Cheats;
+use libgameboii::coverage::Coverage;
 use libgameboii::cpu::CPU;
 use libgameboii::cpu::MACHINE_HZ;
 use libgameboii::debug_log::Log;
-use libgameboii::ppu::PPU;
+use libgameboii::debugger::Debugger;
+use libgameboii::disasm;
+use libgameboii::gdb_stub::GdbStub;
+use libgameboii::joypad;
+use libgameboii::link_cable;
+use libgameboii::movie::Movie;
+use libgameboii::net_link::NetLink;
+use libgameboii::ppu::Shades;
+use libgameboii::printer::Printer;
+use libgameboii::profiler::Profiler;
+use libgameboii::rewind::Rewind;
+use libgameboii::save_state::SaveState;
+use libgameboii::serial::{SerialDevice, TextOutput};
+use libgameboii::symbols::Symbols;
 use opengl_graphics::OpenGL;
 use piston::input::*;
+use std::cell::RefCell;
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+//hold backspace to go back in time, up to this many seconds
+const REWIND_SECONDS: u32 = 20;
+const REWIND_INTERVAL_FRAMES: u32 = 4;
 
 fn dump_ram(ram: &[u8]) -> std::io::Result<()> {
     let mut file = File::create("ramdump.bin")?;
@@ -26,12 +47,125 @@ fn dump_ram(ram: &[u8]) -> std::io::Result<()> {
     Ok(())
 }
 
+//the save state slots live next to the ROM, as game.ss0 .. game.ss9
+fn state_slot_path(rom_path: &str, slot: u8) -> PathBuf {
+    Path::new(rom_path).with_extension(format!("ss{}", slot))
+}
+
+fn slot_for_key(key: keyboard::Key) -> Option<u8> {
+    match key {
+        keyboard::Key::D0 => Some(0),
+        keyboard::Key::D1 => Some(1),
+        keyboard::Key::D2 => Some(2),
+        keyboard::Key::D3 => Some(3),
+        keyboard::Key::D4 => Some(4),
+        keyboard::Key::D5 => Some(5),
+        keyboard::Key::D6 => Some(6),
+        keyboard::Key::D7 => Some(7),
+        keyboard::Key::D8 => Some(8),
+        keyboard::Key::D9 => Some(9),
+        _ => None,
+    }
+}
+
+fn button_for_key(key: keyboard::Key) -> Option<u8> {
+    match key {
+        keyboard::Key::Right => Some(joypad::RIGHT),
+        keyboard::Key::Left => Some(joypad::LEFT),
+        keyboard::Key::Up => Some(joypad::UP),
+        keyboard::Key::Down => Some(joypad::DOWN),
+        keyboard::Key::X => Some(joypad::A),
+        keyboard::Key::Z => Some(joypad::B),
+        keyboard::Key::RShift => Some(joypad::SELECT),
+        keyboard::Key::Return => Some(joypad::START),
+        _ => None,
+    }
+}
+
+fn write_profile(profiler: &Profiler, path: &str, symbols: Option<&Symbols>) -> std::io::Result<()> {
+    profiler.write_flat(&mut BufWriter::new(File::create(path)?), symbols)?;
+
+    let folded = format!("{}.folded", path);
+    profiler.write_collapsed(&mut BufWriter::new(File::create(&folded)?), symbols)?;
+    println!("Wrote the profile to {} and {}", path, folded);
+    Ok(())
+}
+
+//the labels in the .sym file next to the ROM, if any
+fn load_symbols(rom_path: &str) -> Option<Symbols> {
+    Symbols::for_rom(rom_path).unwrap_or_else(|error| {
+        println!("Cannot load the symbols for {}", rom_path);
+        println!("{}", error);
+        std::process::exit(1);
+    })
+}
+
+//prints a ROM bank as RGBDS source
+fn disasm_command(matches: &ArgMatches) {
+    let rom_path = matches.value_of("ROMFILE").unwrap();
+    let rom = libgameboii::open_rom(&rom_path).unwrap_or_else(|error| {
+        println!("Cannot open file: {}", rom_path);
+        println!("{}", error);
+        std::process::exit(1);
+    });
+
+    let parse = |name: &str, radix: u32| {
+        matches.value_of(name).map(|value| {
+            let value = value.trim_start_matches("0x").trim_start_matches('$');
+            usize::from_str_radix(value, radix).unwrap_or_else(|e| {
+                println!("Invalid value for {}", name);
+                println!("{}", e);
+                std::process::exit(1);
+            })
+        })
+    };
+
+    let bank = parse("bank", 10).unwrap();
+    let from = parse("from", 16).unwrap_or(disasm::bank_start(bank) as usize);
+
+    let symbols = load_symbols(rom_path);
+
+    let result = disasm::disassemble_bank(&rom, bank, from as u16).and_then(|instructions| {
+        disasm::write_rgbds(&mut std::io::stdout(), &instructions, symbols.as_ref())
+    });
+    if let Err(error) = result {
+        println!("{}", error);
+        std::process::exit(1);
+    }
+}
+
 fn main() {
     const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
     let matches = App::new("GAMEBOII")
         .version(VERSION)
         .about("It plays the gameboy dance")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("disasm")
+                .about("Disassemble a ROM bank to RGBDS source")
+                .arg(
+                    Arg::with_name("ROMFILE")
+                        .value_name("FILE")
+                        .help("The cartridge ROM to disassemble")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("bank")
+                        .long("bank")
+                        .value_name("N")
+                        .takes_value(true)
+                        .default_value("0")
+                        .help("The ROM bank to disassemble"),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .value_name("ADDR")
+                        .takes_value(true)
+                        .help("Start at this hex address instead of the start of the bank"),
+                ),
+        )
         .arg(
             Arg::with_name("ROMFILE")
                 .value_name("FILE")
@@ -44,6 +178,14 @@ fn main() {
                 .long("log")
                 .help("Write the executable map and the log to file. Very slow"),
         )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .value_name("FILE")
+                .takes_value(true)
+                .conflicts_with("debug_log")
+                .help("Write the CPU state before every instruction in the gameboy-doctor format"),
+        )
         .arg(
             Arg::with_name("speed_mult")
                 .long("speed_mult")
@@ -52,6 +194,112 @@ fn main() {
                 .default_value("1")
                 .help("A clock multiplier to speed up emulation"),
         )
+        .arg(
+            Arg::with_name("link_rom")
+                .long("link")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Run a second cartridge ROM side by side, connected with a link cable"),
+        )
+        .arg(
+            Arg::with_name("link_listen")
+                .long("link-listen")
+                .value_name("[HOST:]PORT")
+                .takes_value(true)
+                .conflicts_with_all(&["link_rom", "link_connect"])
+                .help(
+                    "Wait for another emulator to connect a link cable on this localhost port. \
+                     To let other machines connect, give the interface too, eg. 0.0.0.0:PORT",
+                ),
+        )
+        .arg(
+            Arg::with_name("link_connect")
+                .long("link-connect")
+                .value_name("HOST:PORT")
+                .takes_value(true)
+                .conflicts_with("link_rom")
+                .help("Connect a link cable to another emulator listening at this address"),
+        )
+        .arg(
+            Arg::with_name("printer")
+                .long("printer")
+                .value_name("DIR")
+                .takes_value(true)
+                .conflicts_with_all(&["link_rom", "link_listen", "link_connect"])
+                .help("Plug a Game Boy Printer in the serial port, saving the prints in DIR"),
+        )
+        .arg(
+            Arg::with_name("load_state")
+                .long("load-state")
+                .value_name("FILE")
+                .takes_value(true)
+                .conflicts_with("link_rom")
+                .help("Start from a save state instead of running the boot ROM"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .value_name("FILE")
+                .takes_value(true)
+                .conflicts_with_all(&["link_rom", "link_listen", "link_connect"])
+                .help("Record the joypad input in a movie, starting from --load-state if given"),
+        )
+        .arg(
+            Arg::with_name("play")
+                .long("play")
+                .value_name("FILE")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "record",
+                    "load_state",
+                    "link_rom",
+                    "link_listen",
+                    "link_connect",
+                ])
+                .help("Play back a movie recorded with --record"),
+        )
+        .arg(
+            Arg::with_name("debug")
+                .long("debug")
+                .help("Start in the debugger, reading commands from stdin. F12 breaks into it"),
+        )
+        .arg(
+            Arg::with_name("gdb")
+                .long("gdb")
+                .value_name("PORT")
+                .takes_value(true)
+                .conflicts_with("debug")
+                .help("Wait for gdb to connect on this localhost port before starting"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Count the cycles spent in each routine. Writes a flat profile to FILE \
+                       and the collapsed stacks for flame graphs to FILE.folded"),
+        )
+        .arg(
+            Arg::with_name("cheats")
+                .long("cheats")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Load Game Genie and GameShark codes from FILE, one per line followed by \
+                       a name. F4 turns them all on or off, the debugger toggles each one"),
+        )
+        .arg(
+            Arg::with_name("cdl")
+                .long("cdl")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Log which ROM bytes run as code or are read as data to FILE, adding to \
+                       it if it exists. Prints the coverage of each bank at exit"),
+        )
+        .arg(
+            Arg::with_name("grayscale")
+                .long("grayscale")
+                .help("Draw with evenly spaced grays, like the reference images of test ROMs"),
+        )
         .arg(
             Arg::with_name("headless")
                 .long("headless")
@@ -59,6 +307,11 @@ fn main() {
         )
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("disasm") {
+        disasm_command(matches);
+        return;
+    }
+
     //load the file from command line
     let rom_path = matches.value_of("ROMFILE").unwrap();
 
@@ -71,58 +324,295 @@ fn main() {
         }
     };
 
-    let rom = libgameboii::open_rom(&rom_path).unwrap_or_else(|error| {
-        println!("Cannot open file: {}", rom_path);
-        println!("An error occurred:");
-        println!("{}", error);
-        std::process::exit(1);
+    let load_rom = |path: &str| {
+        libgameboii::open_rom(&path).unwrap_or_else(|error| {
+            println!("Cannot open file: {}", path);
+            println!("An error occurred:");
+            println!("{}", error);
+            std::process::exit(1);
+        })
+    };
+
+    let rom = load_rom(rom_path);
+    let symbols = load_symbols(rom_path);
+    let link_rom = matches.value_of("link_rom").map(load_rom);
+
+    let mut playback = matches.value_of("play").map(|path| {
+        Movie::load(&path).unwrap_or_else(|error| {
+            println!("Cannot load the movie: {}", path);
+            println!("{}", error);
+            std::process::exit(1);
+        })
     });
 
-    //TODO start from a savestate instead.
-    let boot_rom = libgameboii::open_rom(&"ROMs/DMG_ROM.bin").unwrap();
+    let start_state = match playback {
+        Some(ref movie) => movie.start_state().cloned(),
+        None => matches.value_of("load_state").map(|path| {
+            SaveState::load(&path).unwrap_or_else(|error| {
+                println!("Cannot load the save state: {}", path);
+                println!("{}", error);
+                std::process::exit(1);
+            })
+        }),
+    };
+
+    //a save state replaces the whole RAM, so the boot ROM isn't needed
+    let boot_rom = match start_state {
+        Some(_) => vec![0; 0x100],
+        None => libgameboii::open_rom(&"ROMs/DMG_ROM.bin").unwrap(),
+    };
+
+    let net_link = if let Some(addr) = matches.value_of("link_listen") {
+        if addr.contains(':') {
+            println!("Waiting for a link cable on {}", addr);
+            Some(NetLink::listen_on(addr))
+        } else {
+            let port = addr.parse::<u16>().unwrap_or_else(|e| {
+                println!("Invalid value for link-listen");
+                println!("{}", e);
+                std::process::exit(1);
+            });
+            println!("Waiting for a link cable on port {}", port);
+            Some(NetLink::listen(port))
+        }
+    } else if let Some(addr) = matches.value_of("link_connect") {
+        Some(NetLink::connect(addr))
+    } else {
+        None
+    };
+
+    let net_link = net_link.map(|link| {
+        link.unwrap_or_else(|error| {
+            println!("Cannot connect the link cable");
+            println!("{}", error);
+            std::process::exit(1);
+        })
+    });
+
+    let printer = matches
+        .value_of("printer")
+        .map(|dir| Printer::new(PathBuf::from(dir)));
 
     let do_log = matches.is_present("debug_log");
     let headless = matches.is_present("headless");
 
-    let mut log = if do_log { Some(Log::new()) } else { None };
-    let mut ppu = PPU::new();
+    let mut log = if do_log {
+        let mut log = Log::new();
+        if let Some(ref symbols) = symbols {
+            log.set_symbols(symbols.clone());
+        }
+        Some(log)
+    } else if let Some(path) = matches.value_of("trace") {
+        let file = File::create(path).unwrap_or_else(|error| {
+            println!("Cannot create the trace file: {}", path);
+            println!("{}", error);
+            std::process::exit(1);
+        });
+        Some(Log::trace(Box::new(BufWriter::new(file))))
+    } else {
+        None
+    };
+
+    let shades = if matches.is_present("grayscale") {
+        Shades::Grayscale
+    } else {
+        Shades::Default
+    };
     let mut cpu = CPU::new(&rom, &boot_rom);
+    cpu.ppu.shades = shades;
+    if matches.is_present("debug") {
+        let mut debugger = Debugger::stdio();
+        if let Some(ref symbols) = symbols {
+            debugger.set_symbols(symbols.clone());
+        }
+        cpu.debugger = Some(Box::new(debugger));
+    } else if let Some(port) = matches.value_of("gdb") {
+        let port = port.parse::<u16>().unwrap_or_else(|e| {
+            println!("Invalid value for gdb");
+            println!("{}", e);
+            std::process::exit(1);
+        });
+        println!("Waiting for gdb on port {}", port);
+        let stub = GdbStub::listen(port).unwrap_or_else(|error| {
+            println!("Cannot start the gdb stub");
+            println!("{}", error);
+            std::process::exit(1);
+        });
+        cpu.debugger = Some(Box::new(stub));
+    }
+
+    let profile_path = matches.value_of("profile");
+    if profile_path.is_some() {
+        cpu.profiler = Some(Profiler::new());
+    }
+
+    if let Some(path) = matches.value_of("cheats") {
+        cpu.cheats = Cheats::load(path).unwrap_or_else(|error| {
+            println!("Cannot load the cheats");
+            println!("{}", error);
+            std::process::exit(1);
+        });
+        println!("Loaded {} cheats", cpu.cheats.list().len());
+    }
+
+    let cdl_path = matches.value_of("cdl");
+    if let Some(path) = cdl_path {
+        let coverage = if Path::new(path).exists() {
+            Coverage::load(path, rom.len()).unwrap_or_else(|error| {
+                println!("Cannot load the code/data log");
+                println!("{}", error);
+                std::process::exit(1);
+            })
+        } else {
+            Coverage::new(rom.len())
+        };
+        cpu.coverage = Some(RefCell::new(coverage));
+    }
+
+    //the second gameboy on the other end of the link cable, if any
+    let mut linked = None;
+
+    //whatever is plugged in the serial port
+    let mut serial: Box<dyn SerialDevice> = if let Some(ref rom) = link_rom {
+        let (port, linked_port) = link_cable::connect();
+        let mut linked_cpu = CPU::new(rom, &boot_rom);
+        linked_cpu.ppu.shades = shades;
+        linked = Some((linked_cpu, linked_port));
+        Box::new(port)
+    } else if let Some(printer) = printer {
+        Box::new(printer)
+    } else if let Some(net_link) = net_link {
+        Box::new(net_link)
+    } else {
+        Box::new(TextOutput::new(std::io::stdout()))
+    };
 
     let mut current_clock = 0;
+    if let Some(ref state) = start_state {
+        current_clock = state.restore(&mut cpu).unwrap_or_else(|error| {
+            println!("Cannot load the save state");
+            println!("{}", error);
+            std::process::exit(1);
+        });
+    }
+
+    if let Some(ref movie) = playback {
+        movie.check_rom(&cpu).unwrap_or_else(|error| {
+            println!("Cannot play the movie");
+            println!("{}", error);
+            std::process::exit(1);
+        });
+    }
+
+    let record_path = matches.value_of("record");
+    let mut recording = record_path.map(|_| Movie::new(&cpu, start_state.clone()));
 
-    let mut serial_out = std::io::stdout();
-    let mut update = |cpu: &mut CPU, ppu: &mut PPU| {
-        cpu.tick(current_clock, &mut log, &mut serial_out);
-        ppu.tick(cpu, current_clock);
+    //jumping around in time would break the movie
+    let time_travel = recording.is_none() && playback.is_none();
 
-        current_clock += 1;
+    let mut update = |cpu: &mut CPU,
+                      linked: &mut Option<(CPU, link_cable::LinkPort)>,
+                      current_clock: &mut u64,
+                      buttons: u8| {
+        let clock = *current_clock;
+
+        let mut movie_over = false;
+        if let Some(ref mut movie) = recording {
+            movie.record(cpu, clock, buttons);
+        } else if let Some(ref movie) = playback {
+            movie_over = !movie.play(cpu, clock);
+        } else {
+            cpu.set_buttons(buttons);
+        }
+
+        if movie_over {
+            //give the controls back
+            println!("The movie is over");
+            playback = None;
+            if headless {
+                return false;
+            }
+        }
+
+        cpu.tick(clock, &mut log, &mut *serial);
+
+        if let Some(error) = serial.take_error() {
+            println!("The serial device stopped working");
+            println!("{}", error);
+        }
+
+        if let Some(lock_up) = cpu.take_lock_up() {
+            println!(
+                "The CPU locked up: illegal opcode 0x{:02x} at 0x{:04x}",
+                lock_up.opcode, lock_up.pc
+            );
+            //nothing will happen anymore, only a reset gets it out
+            if headless {
+                return false;
+            }
+        }
+
+        if let Some((ref mut linked_cpu, ref mut linked_port)) = *linked {
+            linked_cpu.tick(clock, &mut None, linked_port);
+        }
+
+        *current_clock += 1;
 
         !cpu.should_exit
     };
 
     if headless {
         println!("Running headless");
-        while update(&mut cpu, &mut ppu) {}
+        while update(&mut cpu, &mut linked, &mut current_clock, 0) {}
     } else {
         let mut paused = false;
+        let mut cheats_enabled = true;
+        let mut state_slot = 0;
+        let mut rewind = Rewind::new(REWIND_SECONDS, REWIND_INTERVAL_FRAMES);
+        let mut rewinding = false;
+        let mut buttons = 0;
         // Create an Glutin window.
-        let mut window = window::Window::new(OpenGL::V3_2);
+        let screens = if linked.is_some() { 2 } else { 1 };
+        let mut window = window::Window::new(OpenGL::V3_2, screens);
 
-        while let Some(e) = window.next() {
+        'running: while let Some(e) = window.next() {
             if let Some(ue) = e.update_args() {
-                let clocks = (MACHINE_HZ as f64 * ue.dt) as u64 * speed_mult;
-                for _ in 0..clocks {
-                    if !update(&mut cpu, &mut ppu) {
-                        return;
+                //a linked gameboy can't be rewound along
+                if rewinding && linked.is_none() && time_travel {
+                    if let Some(state) = rewind.rewind(REWIND_INTERVAL_FRAMES) {
+                        current_clock = state.restore(&mut cpu).unwrap();
+                    }
+                } else {
+                    let clocks = (MACHINE_HZ as f64 * ue.dt) as u64 * speed_mult;
+                    for _ in 0..clocks {
+                        rewind.tick(&cpu, current_clock);
+                        if !update(&mut cpu, &mut linked, &mut current_clock, buttons) {
+                            break 'running;
+                        }
                     }
                 }
             }
 
             if let Some(r) = e.render_args() {
-                window.render(&r, &ppu);
+                match linked {
+                    Some((ref linked_cpu, _)) => window.render(&r, &[&cpu.ppu, &linked_cpu.ppu]),
+                    None => window.render(&r, &[&cpu.ppu]),
+                }
             }
 
             if let Some(i) = e.button_args() {
+                if let Button::Keyboard(k) = i.button {
+                    if k == keyboard::Key::Backspace {
+                        rewinding = i.state == ButtonState::Press;
+                    } else if let Some(button) = button_for_key(k) {
+                        if i.state == ButtonState::Press {
+                            buttons |= button;
+                        } else {
+                            buttons &= !button;
+                        }
+                    }
+                }
+
                 if i.state == ButtonState::Press {
                     match i.button {
                         Button::Keyboard(k) => {
@@ -130,6 +620,36 @@ fn main() {
                                 paused = !paused;
                             } else if k == keyboard::Key::F1 {
                                 dump_ram(&cpu.RAM).unwrap();
+                            } else if k == keyboard::Key::F4 && !cpu.cheats.is_empty() {
+                                cheats_enabled = !cheats_enabled;
+                                cpu.cheats.set_all(cheats_enabled);
+                                let state = if cheats_enabled { "on" } else { "off" };
+                                println!("Cheats {}", state);
+                            } else if k == keyboard::Key::F12 {
+                                if let Some(ref mut debugger) = cpu.debugger {
+                                    debugger.pause();
+                                }
+                            } else if k == keyboard::Key::F2 {
+                                let path = state_slot_path(rom_path, state_slot);
+                                match SaveState::capture(&cpu, current_clock).save(&path) {
+                                    Ok(()) => println!("Saved state {}", state_slot),
+                                    Err(error) => println!("Cannot save the state: {}", error),
+                                }
+                            } else if k == keyboard::Key::F3 && time_travel {
+                                let path = state_slot_path(rom_path, state_slot);
+                                let loaded = SaveState::load(&path)
+                                    .and_then(|state| state.restore(&mut cpu));
+                                match loaded {
+                                    Ok(clock) => {
+                                        current_clock = clock;
+                                        rewind.clear();
+                                        println!("Loaded state {}", state_slot);
+                                    }
+                                    Err(error) => println!("Cannot load the state: {}", error),
+                                }
+                            } else if let Some(slot) = slot_for_key(k) {
+                                state_slot = slot;
+                                println!("Save state slot {}", state_slot);
                             }
                         }
                         _ => (),
@@ -138,4 +658,25 @@ fn main() {
             }
         }
     }
+
+    if let (Some(profiler), Some(path)) = (cpu.profiler.as_ref(), profile_path) {
+        if let Err(error) = write_profile(profiler, path, symbols.as_ref()) {
+            println!("Cannot write the profile: {}", error);
+        }
+    }
+
+    if let (Some(coverage), Some(path)) = (cpu.coverage.as_ref(), cdl_path) {
+        let coverage = coverage.borrow();
+        if let Err(error) = coverage.save(path) {
+            println!("Cannot write the code/data log: {}", error);
+        }
+        coverage.write_summary(&mut std::io::stdout()).unwrap();
+    }
+
+    if let (Some(movie), Some(path)) = (recording, record_path) {
+        match movie.save(&path) {
+            Ok(()) => println!("Recorded {} frames to {}", movie.len(), path),
+            Err(error) => println!("Cannot save the movie: {}", error),
+        }
+    }
 }
@@ -16,12 +16,12 @@ use piston::window::WindowSettings;
 pub struct Window {
     pub window: GlutinWindow,
     pub gl: GlGraphics,
-    pub screen_texture: Texture,
+    pub screen_textures: Vec<Texture>,
     pub events: Events,
 }
 
 impl Window {
-    pub fn new(gl_version: OpenGL) -> Self {
+    pub fn new(gl_version: OpenGL, screens: u32) -> Self {
         let scale = 4;
 
         let mut texture_settings = TextureSettings::new();
@@ -34,7 +34,10 @@ impl Window {
         Window {
             window: WindowSettings::new(
                 "gameboii",
-                [RESOLUTION_W as u32 * scale, RESOLUTION_H as u32 * scale],
+                [
+                    RESOLUTION_W as u32 * scale * screens,
+                    RESOLUTION_H as u32 * scale,
+                ],
             ).opengl(gl_version)
                 .exit_on_esc(true)
                 .build()
@@ -48,7 +51,9 @@ impl Window {
                 ups_reset: 2,
             }),
             gl: GlGraphics::new(gl_version),
-            screen_texture: Texture::from_image(&img, &texture_settings),
+            screen_textures: (0..screens)
+                .map(|_| Texture::from_image(&img, &texture_settings))
+                .collect(),
         }
     }
 
@@ -56,27 +61,32 @@ impl Window {
         self.events.next(&mut self.window)
     }
 
-    pub fn render(&mut self, args: &RenderArgs, ppu: &PPU) {
+    pub fn render(&mut self, args: &RenderArgs, ppus: &[&PPU]) {
         //video update
         use graphics::*;
 
         const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
 
-        //send the cpu-made texture to the CPU
-        self.screen_texture.update(&ppu.screen_buffer);
-
         let c = self.gl.draw_begin(args.viewport());
 
         // Clear the screen.
         graphics::clear(GREEN, &mut self.gl);
 
-        let transform = c.transform.scale(
-            args.viewport().window_size[0] as f64 / RESOLUTION_W as f64,
-            args.viewport().window_size[1] as f64 / RESOLUTION_H as f64,
-        );
+        //linked gameboys are drawn side by side
+        let screen_w = args.viewport().window_size[0] as f64 / ppus.len() as f64;
+        let screen_h = args.viewport().window_size[1] as f64;
+
+        for (idx, (texture, ppu)) in self.screen_textures.iter_mut().zip(ppus).enumerate() {
+            //send the cpu-made texture to the CPU
+            texture.update(&ppu.screen_buffer);
 
-        // Draw a box rotating around the middle of the screen.
-        graphics::image(&self.screen_texture, transform, &mut self.gl);
+            let transform = c.transform.trans(screen_w * idx as f64, 0.0).scale(
+                screen_w / RESOLUTION_W as f64,
+                screen_h / RESOLUTION_H as f64,
+            );
+
+            graphics::image(texture, transform, &mut self.gl);
+        }
         self.gl.draw_end();
     }
 }
@@ -21,6 +21,8 @@ struct OpCodeDesc {
     operands: Vec<String>,
     bytes: usize,
     cycles: usize,
+    //for conditional branches, cycles is when they're taken
+    cycles_not_taken: Option<usize>,
     flagsZNHC: Vec<String>,
 }
 
@@ -64,7 +66,7 @@ enum HLMagic {
     Dec,
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 enum ParameterType {
     U16,
     U8,
@@ -378,7 +380,7 @@ pub unsafe fn interpret(instruction: u16, cpu: &mut CPU) {
 "#;
 
 const FOOTER: &str = r#"
-        _ => panic!("instruction not known")
+        _ => cpu.lock_up(instruction as u8),
     }
 }"#;
 
@@ -434,9 +436,6 @@ fn write_opcodes(
             }
         }
 
-        //add the function to the list
-        function_list.push(function);
-
         //set known flags
         write_flag_handler(outfile, "z", &opcode.flagsZNHC[0])?;
         write_flag_handler(outfile, "n", &opcode.flagsZNHC[1])?;
@@ -444,7 +443,25 @@ fn write_opcodes(
         write_flag_handler(outfile, "c", &opcode.flagsZNHC[3])?;
 
         //cycle
-        writeln!(outfile, "\t\t\tcpu.run_cycles({});", opcode.cycles)?;
+        match opcode.cycles_not_taken {
+            Some(not_taken) => {
+                //the condition is read into one of the parameters, before the branch runs
+                let condition = function
+                    .inputs
+                    .iter()
+                    .position(|param| param.param_type == ParameterType::Bool)
+                    .expect("A conditional branch without a condition");
+                writeln!(
+                    outfile,
+                    "\t\t\tcpu.run_cycles(if reg{} {{ {} }} else {{ {} }});",
+                    condition, opcode.cycles, not_taken
+                )?;
+            }
+            None => writeln!(outfile, "\t\t\tcpu.run_cycles({});", opcode.cycles)?,
+        }
+
+        //add the function to the list
+        function_list.push(function);
 
         writeln!(outfile, "\t\t}},")?;
     }
@@ -487,8 +504,57 @@ fn write_function_stub(
     Ok(())
 }
 
+fn write_opcode_entry(outfile: &mut File, opcode: Option<&OpCodeDesc>) -> std::io::Result<()> {
+    let opcode = match opcode {
+        Some(opcode) => opcode,
+        None => return writeln!(outfile, "\tNone,"),
+    };
+
+    let operands: Vec<String> = opcode
+        .operands
+        .iter()
+        .map(|op| format!("\"{}\"", op))
+        .collect();
+    let flags: Vec<String> = opcode
+        .flagsZNHC
+        .iter()
+        .map(|flag| format!("\"{}\"", flag))
+        .collect();
+
+    writeln!(
+        outfile,
+        "\tSome(OpCode {{ mnemonic: \"{}\", operands: &[{}], bytes: {}, cycles: {}, cycles_not_taken: {:?}, flags: [{}] }}),",
+        opcode.mnemonic,
+        operands.join(", "),
+        opcode.bytes,
+        opcode.cycles,
+        opcode.cycles_not_taken,
+        flags.join(", ")
+    )
+}
+
+//the instruction set as static data, so nothing needs opcodes.json at runtime
+fn write_opcode_table(opcodes: &BTreeMap<String, OpCodeDesc>) -> std::io::Result<()> {
+    let outfile = &mut File::create(OPCODE_TABLE_PATH)?;
+
+    writeln!(outfile, "//generated by build.rs from opcodes.json")?;
+    writeln!(outfile, "use disasm::OpCode;")?;
+
+    for (name, prefix) in &[("OPCODES", ""), ("CB_OPCODES", "cb")] {
+        writeln!(outfile)?;
+        writeln!(outfile, "pub static {}: [Option<OpCode>; 256] = [", name)?;
+        for instr in 0..256 {
+            let opcode = opcodes.get(&format!("0x{}{:02x}", prefix, instr));
+            write_opcode_entry(outfile, opcode)?;
+        }
+        writeln!(outfile, "];")?;
+    }
+    Ok(())
+}
+
 const STUBS_PATH: &str = "src/function_stubs.rs";
 const INTERPRETER_PATH: &str = "src/interpreter.rs";
+const OPCODE_TABLE_PATH: &str = "src/opcode_table.rs";
 const OPCODES_PATH: &str = "opcodes.json";
 
 fn parse_function_stubs() -> std::io::Result<FunctionCodeMap> {
@@ -585,4 +651,6 @@ fn main() {
     let functions = write_interpreter(&opcodes, codes).unwrap();
 
     write_function_stubs(&functions, codes).unwrap();
+
+    write_opcode_table(&opcodes).unwrap();
 }
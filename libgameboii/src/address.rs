@@ -32,12 +32,17 @@ pub const INTERRUPT: [usize; 5] = [0x40, 0x48, 0x50, 0x58, 0x60];
 pub const COLOR_GB_ENABLE: usize = 0x143;
 pub const SUPER_GB_ENABLE: usize = 0x146;
 pub const CARTRIDGE_TYPE: usize = 0x147;
+pub const CARTRIDGE_RAM_SIZE: usize = 0x149;
 
 pub const UNSIGNED_TILE_DATA_TABLE: Range<usize> = 0x8000..0x8800;
 pub const SIGNED_TILE_DATA_TABLE: Range<usize> = 0x8800..0x9800;
 pub const TILE_MAP0: Range<usize> = 0x9800..0x9c00;
 pub const TILE_MAP1: Range<usize> = 0x9C00..0xA000;
 
+pub const EXTERNAL_RAM: Range<usize> = 0xa000..0xc000;
+pub const WORK_RAM: Range<usize> = 0xc000..0xe000;
+pub const HIGH_RAM: Range<usize> = 0xff80..0xffff;
+
 pub const ECHO_MEM_TARGET: Range<usize> = 0xc000..0xde00;
 pub const ECHO_MEM: Range<usize> = 0xe000..0xfe00;
 
@@ -111,9 +116,9 @@ pub const OBP0_REGISTER: usize = 0xff48;
 
 pub const OBP1_REGISTER: usize = 0xff49;
 
-pub const WX_REGISTER: usize = 0xff4a;
+pub const WY_REGISTER: usize = 0xff4a;
 
-pub const WY_REGISTER: usize = 0xff4b;
+pub const WX_REGISTER: usize = 0xff4b;
 
 pub const INTERNAL_ROM_TURN_OFF: usize = 0xff50;
 
@@ -152,18 +157,12 @@ pub fn check_unimplemented_read(addr: usize) {
     if addr == NR44_REGISTER {
         panic!("{:04x} address unimplemented", NR44_REGISTER);
     }
-    if addr == NR52_REGISTER {
-        panic!("{:04x} address unimplemented", NR52_REGISTER);
-    }
     if addr == STAT_REGISTER {
         panic!("{:04x} address unimplemented", STAT_REGISTER);
     }
 }
 
 pub fn check_unimplemented(addr: usize) {
-    if addr >= SPRITE_ATTRIBUTE_TABLE.start && addr < SPRITE_ATTRIBUTE_TABLE.end {
-        panic!("{:04x} address unimplemented", SPRITE_ATTRIBUTE_TABLE.start);
-    }
     if addr == P1_REGISTER {
         // panic!("{:04x} address unimplemented", P1_REGISTER);
     }
@@ -252,10 +251,4 @@ pub fn check_unimplemented(addr: usize) {
     if addr == LYC_REGISTER {
         panic!("{:04x} address unimplemented", LYC_REGISTER);
     }
-    if addr == WX_REGISTER {
-        panic!("{:04x} address unimplemented", WX_REGISTER);
-    }
-    if addr == WY_REGISTER {
-        panic!("{:04x} address unimplemented", WY_REGISTER);
-    }
 }
@@ -0,0 +1,207 @@
+extern crate std;
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheatCode {
+    //patches a ROM read. With a compare value, only when the ROM has it there,
+    //so the code doesn't hit the other banks switched in at the same address
+    GameGenie {
+        addr: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+    //written to RAM every frame. The bank is the cartridge RAM bank, other RAM ignores it
+    GameShark {
+        bank: u8,
+        value: u8,
+        addr: u16,
+    },
+}
+
+fn invalid_code(code: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid cheat code {}", code),
+    )
+}
+
+impl CheatCode {
+    //Game Genie: ABC-DEF or ABC-DEF-GHI. GameShark: ABCDEFGH
+    pub fn parse(code: &str) -> io::Result<Self> {
+        let digits = code
+            .chars()
+            .filter(|c| *c != '-')
+            .map(|c| c.to_digit(16).map(|digit| digit as u8))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| invalid_code(code))?;
+        let byte = |idx: usize| (digits[idx] << 4) | digits[idx + 1];
+
+        match digits.len() {
+            8 if !code.contains('-') => {
+                //the address is little endian, and the ROM can't be written
+                let addr = (byte(6) as u16) << 8 | byte(4) as u16;
+                if addr < 0x8000 {
+                    return Err(invalid_code(code));
+                }
+
+                Ok(CheatCode::GameShark {
+                    bank: byte(0),
+                    value: byte(2),
+                    addr: addr,
+                })
+            }
+            6 | 9 => {
+                //the top digit of the address is scrambled, and it can only point into ROM
+                let addr = ((digits[5] ^ 0xf) as u16) << 12
+                    | (digits[2] as u16) << 8
+                    | (digits[3] as u16) << 4
+                    | digits[4] as u16;
+                if addr >= 0x8000 {
+                    return Err(invalid_code(code));
+                }
+
+                //digit H isn't used
+                let compare = if digits.len() == 9 {
+                    Some(((digits[6] << 4) | digits[8]).rotate_right(2) ^ 0xba)
+                } else {
+                    None
+                };
+
+                Ok(CheatCode::GameGenie {
+                    addr: addr,
+                    value: byte(0),
+                    compare: compare,
+                })
+            }
+            _ => Err(invalid_code(code)),
+        }
+    }
+}
+
+impl fmt::Display for CheatCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CheatCode::GameGenie {
+                addr,
+                value,
+                compare: Some(compare),
+            } => write!(f, "{:04x}: {:02x} if {:02x}", addr, value, compare),
+            CheatCode::GameGenie { addr, value, .. } => write!(f, "{:04x}: {:02x}", addr, value),
+            CheatCode::GameShark { addr, value, .. } => {
+                write!(f, "{:04x} = {:02x} every frame", addr, value)
+            }
+        }
+    }
+}
+
+pub struct Cheat {
+    pub code: CheatCode,
+    pub name: String,
+    pub enabled: bool,
+}
+
+//the cheats the CPU applies. They start enabled
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats { cheats: vec![] }
+    }
+
+    //one code per line, then an optional name: "010238CD Infinite lives"
+    pub fn parse<R: BufRead>(input: R) -> io::Result<Self> {
+        let mut cheats = Self::new();
+
+        for (idx, line) in input.lines().enumerate() {
+            let line = line?;
+            let line = line.split(';').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let code = parts.next().unwrap();
+            let name = parts.next().unwrap_or("").trim();
+            cheats.add(code, name).map_err(|error| {
+                io::Error::new(error.kind(), format!("{} on line {}", error, idx + 1))
+            })?;
+        }
+
+        Ok(cheats)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(BufReader::new(File::open(path)?))
+    }
+
+    pub fn add(&mut self, code: &str, name: &str) -> io::Result<()> {
+        self.cheats.push(Cheat {
+            code: CheatCode::parse(code)?,
+            name: name.to_owned(),
+            enabled: true,
+        });
+        Ok(())
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    //returns whether the cheat is on now, None if there's no such cheat
+    pub fn toggle(&mut self, idx: usize) -> Option<bool> {
+        let cheat = self.cheats.get_mut(idx)?;
+        cheat.enabled = !cheat.enabled;
+        Some(cheat.enabled)
+    }
+
+    pub fn set_all(&mut self, enabled: bool) {
+        for cheat in &mut self.cheats {
+            cheat.enabled = enabled;
+        }
+    }
+
+    fn enabled(&self) -> impl Iterator<Item = &CheatCode> {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .map(|cheat| &cheat.code)
+    }
+
+    //what a ROM read returns once the Game Genie had its say
+    pub fn patch_rom(&self, addr: u16, val: u8) -> u8 {
+        for code in self.enabled() {
+            if let CheatCode::GameGenie {
+                addr: patched,
+                value,
+                compare,
+            } = *code
+            {
+                if patched == addr && compare.map(|compare| compare == val).unwrap_or(true) {
+                    return value;
+                }
+            }
+        }
+        val
+    }
+
+    //the GameShark writes, done at vblank. write gets the bank, the address and the value,
+    //the CPU knows where that bank is
+    pub fn write_ram<F: FnMut(u8, u16, u8)>(&self, mut write: F) {
+        for code in self.enabled() {
+            if let CheatCode::GameShark { bank, value, addr } = *code {
+                write(bank, addr, value);
+            }
+        }
+    }
+}
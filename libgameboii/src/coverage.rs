@@ -0,0 +1,138 @@
+extern crate std;
+
+use disasm::BANK_SIZE;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+
+//how a ROM byte was used, one bit each. A byte can be more than one
+pub const OPCODE: u8 = 0x01;
+pub const OPERAND: u8 = 0x02;
+pub const DATA: u8 = 0x04;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BankCoverage {
+    pub size: usize,
+    pub opcode: usize,
+    pub operand: usize,
+    pub data: usize,
+    //bytes that were never touched
+    pub unused: usize,
+}
+
+impl BankCoverage {
+    pub fn used(&self) -> usize {
+        self.size - self.unused
+    }
+}
+
+//the code/data log: one byte of flags for every byte of the ROM.
+//the file is the same size as the ROM, so tools can line them up
+pub struct Coverage {
+    flags: Vec<u8>,
+}
+
+impl Coverage {
+    pub fn new(rom_size: usize) -> Self {
+        Coverage {
+            flags: vec![0; rom_size],
+        }
+    }
+
+    //carries on from an earlier run, so several play sessions add up
+    pub fn load<P: AsRef<Path>>(path: P, rom_size: usize) -> io::Result<Self> {
+        let mut flags = vec![];
+        File::open(path)?.read_to_end(&mut flags)?;
+        if flags.len() != rom_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The CDL file has {} bytes but the ROM has {}",
+                    flags.len(),
+                    rom_size
+                ),
+            ));
+        }
+        Ok(Coverage { flags: flags })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        File::create(path)?.write_all(&self.flags)
+    }
+
+    pub fn flags(&self) -> &[u8] {
+        &self.flags
+    }
+
+    //offset is into the whole ROM, not the address it's mapped at
+    pub fn mark(&mut self, offset: usize, usage: u8) {
+        if let Some(flags) = self.flags.get_mut(offset) {
+            *flags |= usage;
+        }
+    }
+
+    pub fn bank_count(&self) -> usize {
+        (self.flags.len() + BANK_SIZE - 1) / BANK_SIZE
+    }
+
+    pub fn bank(&self, bank: usize) -> BankCoverage {
+        let start = std::cmp::min(bank * BANK_SIZE, self.flags.len());
+        let end = std::cmp::min(start + BANK_SIZE, self.flags.len());
+
+        let mut coverage = BankCoverage {
+            size: end - start,
+            ..Default::default()
+        };
+        for &flags in &self.flags[start..end] {
+            if flags & OPCODE != 0 {
+                coverage.opcode += 1;
+            }
+            if flags & OPERAND != 0 {
+                coverage.operand += 1;
+            }
+            if flags & DATA != 0 {
+                coverage.data += 1;
+            }
+            if flags == 0 {
+                coverage.unused += 1;
+            }
+        }
+        coverage
+    }
+
+    //a table of the bytes used in each bank
+    pub fn write_summary<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(
+            out,
+            "{:>4} {:>7} {:>7} {:>7} {:>7} {:>8}",
+            "bank", "opcode", "operand", "data", "unused", "used"
+        )?;
+
+        let mut total = BankCoverage::default();
+        for bank in 0..self.bank_count() {
+            let coverage = self.bank(bank);
+            write_row(out, &format!("{:02x}", bank), &coverage)?;
+
+            total.size += coverage.size;
+            total.opcode += coverage.opcode;
+            total.operand += coverage.operand;
+            total.data += coverage.data;
+            total.unused += coverage.unused;
+        }
+        write_row(out, "all", &total)
+    }
+}
+
+fn write_row<W: Write>(out: &mut W, name: &str, coverage: &BankCoverage) -> io::Result<()> {
+    writeln!(
+        out,
+        "{:>4} {:>7} {:>7} {:>7} {:>7} {:>7.2}%",
+        name,
+        coverage.opcode,
+        coverage.operand,
+        coverage.data,
+        coverage.unused,
+        coverage.used() as f64 * 100.0 / std::cmp::max(coverage.size, 1) as f64
+    )
+}
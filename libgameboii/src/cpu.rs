@@ -2,9 +2,21 @@ extern crate std;
 
 use address;
 use bit_field::BitField;
+use cheats::Cheats;
+use coverage;
+use coverage::Coverage;
 use debug_log::Log;
+use debugger::DebugHook;
 use interpreter;
+use joypad;
+use ppu::PPU;
+use profiler::{Profiler, Routine};
+use serial::SerialDevice;
+use std::cell::RefCell;
+use std::cmp;
+use std::io;
 use std::ops::Range;
+use watchpoint::{Access, WatchHit, Watchpoints};
 
 //the RAM size is max addr + 1
 const RAM_SIZE: usize = 0xFFFF + 1;
@@ -12,8 +24,10 @@ pub const MACHINE_HZ: u64 = 4194304;
 const BOOT_ROM: Range<usize> = 0..0x100;
 const ROM_BANK0: Range<usize> = 0..0x4000;
 const ROM_BANK1: Range<usize> = 0x4000..0x8000;
-const DIV_INCREMENT_CLOCKS: u8 = 64 / 4;
-const TIMER_INCREMENT_CLOCKS_MAP: [u16; 4] = [64, 1, 4, 16];
+//in CPU cycles of 4 clocks, which is how often handle_timers runs.
+//DIV counts at 16384Hz, TIMA at 4096, 262144, 65536 or 16384Hz depending on TAC
+const DIV_INCREMENT_CLOCKS: u8 = 64;
+const TIMER_INCREMENT_CLOCKS_MAP: [u16; 4] = [256, 4, 16, 64];
 
 const DMA_BYTE_SIZE: usize = 160;
 const DMA_CYCLES: u64 = 671;
@@ -21,6 +35,10 @@ const DMA_CYCLES: u64 = 671;
 
 const DMA_ONE_BYTE_COPY_DURATION: u64 = DMA_CYCLES / DMA_BYTE_SIZE as u64;
 
+//the internal serial clock runs at 8192Hz
+const SERIAL_BIT_CLOCKS: u16 = (MACHINE_HZ / 8192) as u16;
+const SERIAL_BITS: u8 = 8;
+
 #[derive(Clone, Copy)]
 #[repr(C)]
 pub struct RegisterPair {
@@ -35,6 +53,7 @@ pub union Register {
 }
 
 #[allow(non_snake_case)]
+#[derive(Clone, Serialize, Deserialize)]
 struct DMATransfer {
     bytes_copied: usize,
     current_address: usize,
@@ -52,8 +71,31 @@ impl DMATransfer {
     }
 }
 
-const MBC1_MEMORY_MODE_SELECT: Range<usize> = 0x6000..0x8000;
+#[derive(Clone, Serialize, Deserialize)]
+struct SerialTransfer {
+    internal_clock: bool,
+    bits_left: u8,
+    clocks_to_next_bit: u16,
+    //the answer of devices that work with whole bytes
+    incoming: Option<u8>,
+}
+
+impl SerialTransfer {
+    fn from_reg(reg: u8) -> Self {
+        SerialTransfer {
+            internal_clock: reg.get_bit(0),
+            bits_left: SERIAL_BITS,
+            clocks_to_next_bit: SERIAL_BIT_CLOCKS,
+            incoming: None,
+        }
+    }
+}
+
+const MBC1_RAM_ENABLE: Range<usize> = 0x0000..0x2000;
 const MBC1_ROM_BANK_SELECT: Range<usize> = 0x2000..0x4000;
+const MBC1_UPPER_BANK_SELECT: Range<usize> = 0x4000..0x6000;
+const MBC1_MEMORY_MODE_SELECT: Range<usize> = 0x6000..0x8000;
+const EXTERNAL_RAM_BANK_SIZE: usize = 0x2000;
 
 fn find_highest_prio_interrupt(enabled_and_requested: u8) -> usize {
     for i in 0..5 {
@@ -64,39 +106,207 @@ fn find_highest_prio_interrupt(enabled_and_requested: u8) -> usize {
     panic!("Only call this if any interrupt is requested");
 }
 
-//TODO RAM switching for MBC1 + RAM
+fn external_ram_banks(cart: &[u8]) -> usize {
+    match cart[address::CARTRIDGE_RAM_SIZE] {
+        0x00 => 0,
+        //2kB and 8kB both fit in a single bank
+        0x01 | 0x02 => 1,
+        0x03 => 4,
+        0x04 => 16,
+        0x05 => 8,
+        _ => panic!("Cartridge RAM size not supported"),
+    }
+}
 
-#[allow(non_snake_case)]
-struct MBC1<'a> {
-    _cartridge_rom: &'a [u8],
+//the selected banks are copied into RAM, where the CPU reads them from.
+//the cartridge RAM banks that aren't selected are kept here
+#[derive(Clone, Serialize, Deserialize)]
+struct MBC1 {
+    rom_banks: usize,
+    ram_banks: Vec<Vec<u8>>,
+    ram_enabled: bool,
+
+    //the low 5 bits of the ROM bank
+    rom_bank_select: u8,
+    //2 more bits: the top of the ROM bank, or the RAM bank
+    upper_bank_select: u8,
+    //the upper bits also switch the bank at 0x0000 and the RAM bank
+    advanced_mode: bool,
+
+    //what is in RAM right now
+    mapped_rom_banks: [usize; 2],
+    mapped_ram_bank: usize,
 }
 
-impl<'a> MBC1<'a> {
-    fn from_cart(cart: &'a [u8]) -> MBC1<'a> {
+impl MBC1 {
+    fn from_cart(cart: &[u8]) -> MBC1 {
         MBC1 {
-            _cartridge_rom: cart,
+            rom_banks: cmp::max(cart.len() / ROM_BANK1.len(), 2),
+            ram_banks: vec![vec![0; EXTERNAL_RAM_BANK_SIZE]; external_ram_banks(cart)],
+            ram_enabled: false,
+            rom_bank_select: 1,
+            upper_bank_select: 0,
+            advanced_mode: false,
+            mapped_rom_banks: [0, 1],
+            mapped_ram_bank: 0,
         }
     }
 
-    fn handle_write(&self, addr: usize, _val: u8, _ram: &mut [u8]) -> bool {
-        if addr >= ROM_BANK0.start && addr < ROM_BANK1.end {
-            panic!("Not implemented");
+    //returns true when the write went to the MBC instead of RAM
+    fn handle_write(&mut self, addr: usize, val: u8, rom: &[u8], ram: &mut [u8]) -> bool {
+        if address::in_range(MBC1_RAM_ENABLE, addr) {
+            self.ram_enabled = val & 0x0f == 0x0a;
+        } else if address::in_range(MBC1_ROM_BANK_SELECT, addr) {
+            //bank 0 can't be selected there, it becomes 1
+            self.rom_bank_select = cmp::max(val & 0x1f, 1);
+        } else if address::in_range(MBC1_UPPER_BANK_SELECT, addr) {
+            self.upper_bank_select = val & 0x03;
+        } else if address::in_range(MBC1_MEMORY_MODE_SELECT, addr) {
+            self.advanced_mode = val.get_bit(0);
+        } else if address::in_range(address::EXTERNAL_RAM, addr) {
+            //writes only get through to RAM that is there and enabled
+            return !self.ram_readable();
+        } else {
+            return false;
         }
 
-        if address::in_range(MBC1_ROM_BANK_SELECT, addr) {
-            //TODO copy the selected bank into ram
-            panic!("Not implemented");
-        } else if address::in_range(MBC1_MEMORY_MODE_SELECT, addr) {
-            panic!("Not implemented");
+        self.map_banks(rom, ram);
+        true
+    }
+
+    fn ram_readable(&self) -> bool {
+        self.ram_enabled && !self.ram_banks.is_empty()
+    }
+
+    //writes into a cartridge RAM bank, whether it's mapped or not
+    fn poke_ram_bank(&mut self, bank: usize, addr: usize, val: u8, ram: &mut [u8]) {
+        if self.ram_banks.is_empty() {
+            return;
+        }
+
+        let bank = bank % self.ram_banks.len();
+        if bank == self.mapped_ram_bank {
+            ram[addr] = val;
+        } else {
+            self.ram_banks[bank][addr - address::EXTERNAL_RAM.start] = val;
+        }
+    }
+
+    fn map_banks(&mut self, rom: &[u8], ram: &mut [u8]) {
+        let upper = (self.upper_bank_select as usize) << 5;
+        let bank0 = if self.advanced_mode { upper } else { 0 };
+        let bank1 = upper | self.rom_bank_select as usize;
+
+        for (area, &bank) in [ROM_BANK0, ROM_BANK1].iter().zip(&[bank0, bank1]) {
+            //the bank number wraps around the size of the ROM
+            let bank = bank % self.rom_banks;
+            let idx = area.start / ROM_BANK1.len();
+            if self.mapped_rom_banks[idx] != bank {
+                let start = bank * ROM_BANK1.len();
+                ram[area.clone()].copy_from_slice(&rom[start..start + ROM_BANK1.len()]);
+                self.mapped_rom_banks[idx] = bank;
+            }
         }
 
-        false
+        let ram_bank = if self.advanced_mode && !self.ram_banks.is_empty() {
+            self.upper_bank_select as usize % self.ram_banks.len()
+        } else {
+            0
+        };
+        if ram_bank != self.mapped_ram_bank {
+            self.ram_banks[self.mapped_ram_bank].copy_from_slice(&ram[address::EXTERNAL_RAM]);
+            ram[address::EXTERNAL_RAM].copy_from_slice(&self.ram_banks[ram_bank]);
+            self.mapped_ram_bank = ram_bank;
+        }
     }
 }
 
-enum ROMController<'a> {
+#[derive(Clone, Serialize, Deserialize)]
+enum ROMController {
     ROMOnly,
-    MBC1(MBC1<'a>),
+    MBC1(MBC1),
+}
+
+impl ROMController {
+    //the ROM bank mapped at this address
+    fn rom_bank(&self, addr: usize) -> usize {
+        let idx = if addr < ROM_BANK0.end { 0 } else { 1 };
+        match *self {
+            ROMController::ROMOnly => idx,
+            ROMController::MBC1(ref mbc) => mbc.mapped_rom_banks[idx],
+        }
+    }
+
+    fn ram_readable(&self) -> bool {
+        match *self {
+            ROMController::ROMOnly => true,
+            ROMController::MBC1(ref mbc) => mbc.ram_readable(),
+        }
+    }
+
+    //a GameShark write, cartridge RAM goes into the bank the code names
+    fn poke(&mut self, bank: u8, addr: u16, val: u8, ram: &mut [u8]) {
+        let addr = addr as usize;
+        match *self {
+            ROMController::MBC1(ref mut mbc) if address::in_range(address::EXTERNAL_RAM, addr) => {
+                mbc.poke_ram_bank(bank as usize, addr, val, ram)
+            }
+            _ => ram[addr] = val,
+        }
+    }
+}
+
+//an illegal opcode hangs the CPU until the next reset. The rest of the hardware keeps going
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LockUp {
+    pub pc: u16,
+    pub opcode: u8,
+}
+
+//everything needed to put a CPU back at the same point in time.
+//the selected ROM and cartridge RAM banks live in RAM, the MBC keeps the others
+#[allow(non_snake_case)]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CPUState {
+    PC: u16,
+    SP: u16,
+    AF: u16,
+    BC: u16,
+    DE: u16,
+    HL: u16,
+
+    RAM: Vec<u8>,
+    rom_controller: ROMController,
+
+    boot_mode: bool,
+    DMA_transfer: Option<DMATransfer>,
+    serial_transfer: Option<SerialTransfer>,
+
+    interrupts_master_enabled: bool,
+    interrupts_enable_pending: bool,
+
+    next_clock: u64,
+    hardware_clock: u64,
+
+    div_counter: u8,
+    timer_counter: u16,
+
+    buttons: u8,
+
+    locked_up: Option<LockUp>,
+}
+
+impl CPUState {
+    //what load_state checks before it changes anything
+    pub fn validate(&self) -> io::Result<()> {
+        if self.RAM.len() != RAM_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The save state RAM has the wrong size",
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[allow(non_snake_case)]
@@ -109,22 +319,46 @@ pub struct CPU<'a> {
     pub HL: Register,
 
     pub RAM: [u8; RAM_SIZE],
+    //ticked along with the rest of the hardware, between the memory accesses too
+    pub ppu: PPU,
 
-    rom_controller: ROMController<'a>,
+    rom_controller: ROMController,
+    //no MBC, IO registers or echo memory: every address is plain RAM
+    flat_ram: bool,
 
     boot_mode: bool,
     DMA_transfer: Option<DMATransfer>,
+    serial_transfer: Option<SerialTransfer>,
 
-    interrupt_change_counter: u8,
-    interrupts_master_enabled_next: u8,
-    interrupts_master_enabled: u8,
+    interrupts_master_enabled: bool,
+    interrupts_enable_pending: bool,
 
     next_clock: u64,
+    //the timers, the DMA and the PPU ran up to here. The CPU moves them forward itself
+    //between the memory accesses of an instruction, so this can be ahead of tick
+    hardware_clock: u64,
+    //the clock of the next memory access of the running instruction, None between instructions
+    bus_clock: Option<u64>,
     cartridge_ROM: &'a [u8],
     pub should_exit: bool,
+    pub debugger: Option<Box<dyn DebugHook>>,
+    pub profiler: Option<Profiler>,
+    //marked on reads too, which don't get a mutable CPU
+    pub coverage: Option<RefCell<Coverage>>,
+    pub cheats: Cheats,
+
+    //reads don't get a mutable CPU, but they can still fire the callbacks
+    pub watchpoints: RefCell<Watchpoints>,
+    instruction_PC: u16,
 
     div_counter: u8,
     timer_counter: u16,
+
+    buttons: u8,
+
+    locked_up: Option<LockUp>,
+    //the lock up, until the host takes it
+    lock_up_event: Option<LockUp>,
 }
 
 impl<'a> CPU<'a> {
@@ -141,16 +375,16 @@ impl<'a> CPU<'a> {
             0x0 => {
                 //No MBC, nothing to do
             }
-            0x1 => {
-                //ROM+MBC1. create a MBC1 and give it the ROM
+            0x1..=0x3 => {
+                //ROM+MBC1, with or without RAM and a battery. The RAM isn't saved anywhere yet
                 self.rom_controller = ROMController::MBC1(MBC1::from_cart(rom));
             }
             _ => panic!("Cartridge type not yet supported"),
         }
     }
 
-    pub fn new(rom: &'a [u8], boot_rom: &[u8]) -> CPU<'a> {
-        let mut cpu = CPU {
+    fn blank(rom: &'a [u8]) -> CPU<'a> {
+        CPU {
             PC: 0,
             SP: 0,
             AF: Register { r16: 0 },
@@ -158,23 +392,45 @@ impl<'a> CPU<'a> {
             DE: Register { r16: 0 },
             HL: Register { r16: 0 },
             RAM: [0; RAM_SIZE],
+            ppu: PPU::new(),
 
             rom_controller: ROMController::ROMOnly,
+            flat_ram: false,
 
             boot_mode: true,
             DMA_transfer: None,
+            serial_transfer: None,
 
-            interrupts_master_enabled: 0,
-            interrupt_change_counter: 0,
-            interrupts_master_enabled_next: 0,
+            interrupts_master_enabled: false,
+            interrupts_enable_pending: false,
 
             next_clock: 0,
+            hardware_clock: 0,
+            bus_clock: None,
             cartridge_ROM: rom,
             div_counter: 0,
             timer_counter: 0,
 
+            buttons: 0,
+
+            locked_up: None,
+            lock_up_event: None,
+
             should_exit: false,
-        };
+            debugger: None,
+            profiler: None,
+            coverage: None,
+            cheats: Cheats::new(),
+            watchpoints: RefCell::new(Watchpoints::new()),
+            instruction_PC: 0,
+        }
+    }
+
+    pub fn new(rom: &'a [u8], boot_rom: &[u8]) -> CPU<'a> {
+        let mut cpu = CPU::blank(rom);
+
+        //nothing pressed
+        cpu.RAM[address::P1_REGISTER] = joypad::p1_register(0, 0);
 
         assert!(
             cpu.RAM[address::COLOR_GB_ENABLE] != 0x80,
@@ -193,35 +449,142 @@ impl<'a> CPU<'a> {
         cpu
     }
 
-    pub fn handle_interrupts(&mut self) -> bool {
+    //a CPU on 64k of zeroed RAM with nothing mapped, to test instructions on their own
+    pub fn new_flat_ram() -> CPU<'static> {
+        let mut cpu = CPU::blank(&[]);
+        cpu.flat_ram = true;
+        cpu.boot_mode = false;
+        cpu
+    }
+
+    //the clock the next instruction runs at
+    pub fn next_clock(&self) -> u64 {
+        self.next_clock
+    }
+
+    //the ROM bank mapped at this address
+    pub fn rom_bank(&self, addr: u16) -> usize {
+        self.rom_controller.rom_bank(addr as usize)
+    }
+
+    //where the byte at this address is in the cartridge ROM, if it comes from there
+    pub fn rom_offset(&self, addr: u16) -> Option<usize> {
+        let addr = addr as usize;
+        if self.boot_mode && address::in_range(BOOT_ROM, addr) {
+            None
+        } else if addr < ROM_BANK1.end {
+            Some(self.rom_bank(addr as u16) * ROM_BANK1.len() + addr % ROM_BANK1.len())
+        } else {
+            None
+        }
+    }
+
+    pub fn rom_checksum(&self) -> u32 {
+        ::rom_checksum(self.cartridge_ROM)
+    }
+
+    pub fn save_state(&self) -> CPUState {
+        unsafe {
+            CPUState {
+                PC: self.PC,
+                SP: self.SP,
+                AF: self.AF.r16,
+                BC: self.BC.r16,
+                DE: self.DE.r16,
+                HL: self.HL.r16,
+                RAM: self.RAM.to_vec(),
+                rom_controller: self.rom_controller.clone(),
+                boot_mode: self.boot_mode,
+                DMA_transfer: self.DMA_transfer.clone(),
+                serial_transfer: self.serial_transfer.clone(),
+                interrupts_master_enabled: self.interrupts_master_enabled,
+                interrupts_enable_pending: self.interrupts_enable_pending,
+                next_clock: self.next_clock,
+                hardware_clock: self.hardware_clock,
+                div_counter: self.div_counter,
+                timer_counter: self.timer_counter,
+                buttons: self.buttons,
+                locked_up: self.locked_up,
+            }
+        }
+    }
+
+    pub fn load_state(&mut self, state: &CPUState) -> io::Result<()> {
+        state.validate()?;
+
+        self.PC = state.PC;
+        self.SP = state.SP;
+        self.AF.r16 = state.AF;
+        self.BC.r16 = state.BC;
+        self.DE.r16 = state.DE;
+        self.HL.r16 = state.HL;
+        self.RAM.copy_from_slice(&state.RAM);
+        self.rom_controller = state.rom_controller.clone();
+        self.boot_mode = state.boot_mode;
+        self.DMA_transfer = state.DMA_transfer.clone();
+        self.serial_transfer = state.serial_transfer.clone();
+        self.interrupts_master_enabled = state.interrupts_master_enabled;
+        self.interrupts_enable_pending = state.interrupts_enable_pending;
+        self.next_clock = state.next_clock;
+        self.hardware_clock = state.hardware_clock;
+        self.div_counter = state.div_counter;
+        self.timer_counter = state.timer_counter;
+        self.buttons = state.buttons;
+        self.locked_up = state.locked_up;
+        Ok(())
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.RAM[address::IE_REGISTER] & self.RAM[address::IF_REGISTER] & 0x1f
+    }
+
+    pub fn handle_interrupts(&mut self, current_clock: u64) -> bool {
         // handle interrupts:
         // if any bit of interrupts_requested are set and enabled, start from the
         // highest priority (0) and switch to the interrupt routine
-        let enabled_interrupts = self.RAM[address::IE_REGISTER] & self.interrupts_master_enabled;
+        if !self.interrupts_master_enabled || self.pending_interrupts() == 0 {
+            return false;
+        }
 
         //TODO tetris wants serial transfer?
 
-        let interrupts = self.RAM[address::IF_REGISTER] & enabled_interrupts;
+        // disable the IME
+        self.interrupts_master_enabled = false;
+        self.interrupts_enable_pending = false;
 
-        if interrupts != 0 {
-            // interrupts are available: find the highest priority one
-            let current_interrupt = find_highest_prio_interrupt(interrupts);
+        // the pushes aren't part of any instruction, blame the one that got interrupted.
+        // It takes 5 cycles: two waiting, two pushing PC and one jumping
+        self.instruction_PC = self.PC;
+        self.bus_clock = Some(current_clock);
+        self.internal_cycle();
+        self.internal_cycle();
 
-            // disable the IME
-            self.interrupts_master_enabled = 0;
-            // reset the bit we handled
-            self.RAM[address::IF_REGISTER].set_bit(current_interrupt, false);
+        let pc = self.PC;
+        self.SP = self.SP.wrapping_sub(1);
+        let sp = self.SP;
+        self.set_address(sp, (pc >> 8) as u8);
 
-            // call the new address
-            let addr = address::INTERRUPT[current_interrupt] as u16;
-            self.call(addr);
+        // the interrupt is only picked now: when SP was 0, the high byte of PC just went
+        // into IE. If that turned off every requested interrupt, it jumps to 0 instead
+        let interrupts = self.pending_interrupts();
 
-            // then wait 5 cycles (according to the wiki?)
-            self.run_cycles(5);
+        self.SP = self.SP.wrapping_sub(1);
+        let sp = self.SP;
+        self.set_address(sp, pc as u8);
 
-            return true;
-        }
-        return false;
+        let addr = if interrupts != 0 {
+            // find the highest priority one and reset its bit
+            let current_interrupt = find_highest_prio_interrupt(interrupts);
+            self.RAM[address::IF_REGISTER].set_bit(current_interrupt, false);
+            address::INTERRUPT[current_interrupt] as u16
+        } else {
+            0
+        };
+        self.jump_to_routine(addr);
+        self.bus_clock = None;
+
+        self.run_cycles(20);
+        true
     }
 
     fn handle_dma(&mut self, current_clock: u64) {
@@ -249,24 +612,108 @@ impl<'a> CPU<'a> {
         }
     }
 
-    fn handle_serial_transfer<W: std::io::Write>(&mut self, sink: &mut W) {
-        //for now, just copy out immediately
-        if self.RAM[address::SC_REGISTER].get_bit(7) {
-            let mut out = 0;
-            std::mem::swap(&mut out, &mut self.RAM[address::SB_REGISTER]);
+    fn handle_serial_transfer<D: SerialDevice + ?Sized>(&mut self, device: &mut D) {
+        //the device gets to drive the clock when we're waiting for it
+        let listening = match self.serial_transfer {
+            Some(ref transfer) => !transfer.internal_clock,
+            None => false,
+        };
+        let line = if listening {
+            Some(self.RAM[address::SB_REGISTER].get_bit(7))
+        } else {
+            None
+        };
+        if let Some(bit_in) = device.external_clock(line) {
+            if listening {
+                self.shift_serial_bit(bit_in);
+            }
+        }
+
+        let mut pulse = false;
+        if let Some(ref mut transfer) = self.serial_transfer {
+            if transfer.internal_clock {
+                transfer.clocks_to_next_bit -= 1;
+                if transfer.clocks_to_next_bit == 0 {
+                    transfer.clocks_to_next_bit = SERIAL_BIT_CLOCKS;
+                    pulse = true;
+                }
+            }
+        }
+
+        if pulse {
+            let sb = self.RAM[address::SB_REGISTER];
+            let bit_in = match device.exchange_bit(sb.get_bit(7)) {
+                Some(bit) => bit,
+                None => {
+                    //ask the whole byte on the first bit, then shift it in bit by bit
+                    let transfer = self.serial_transfer.as_mut().unwrap();
+                    if transfer.incoming.is_none() {
+                        transfer.incoming = Some(device.exchange_byte(sb));
+                    }
+                    let incoming = transfer.incoming.unwrap();
+                    incoming.get_bit(transfer.bits_left as usize - 1)
+                }
+            };
+            self.shift_serial_bit(bit_in);
+        }
+    }
+
+    fn shift_serial_bit(&mut self, bit_in: bool) {
+        let done = match self.serial_transfer {
+            Some(ref mut transfer) => {
+                transfer.bits_left -= 1;
+                transfer.bits_left == 0
+            }
+            None => return,
+        };
+
+        //the top bit goes out, the incoming one is shifted in at the bottom
+        let sb = self.RAM[address::SB_REGISTER];
+        self.RAM[address::SB_REGISTER] = (sb << 1) | bit_in as u8;
 
-            //whatever the game might want to say? Let's assume it's chars
-            write!(sink, "{}", out as char).unwrap();
+        if done {
+            self.serial_transfer = None;
 
             //and stop the transfer
-            //TODO do it after a bunch of clocks
             self.RAM[address::SC_REGISTER].set_bit(7, false);
-
-            //trigger an interrupt //TODO let time pass
             self.request_serial_transfer_interrupt();
         }
     }
 
+    //runs the DMA and the timers for the clocks before this one that they didn't see yet
+    fn run_hardware(&mut self, clock: u64) {
+        while self.hardware_clock < clock {
+            let current_clock = self.hardware_clock;
+            self.handle_dma(current_clock);
+
+            //only on CPU clocks
+            if current_clock % 4 == 0 {
+                self.handle_timers();
+            }
+
+            let dma_in_progress = self.is_dma_mode();
+            if self.ppu.tick(&mut self.RAM, dma_in_progress, current_clock) {
+                self.request_vblank();
+            }
+            self.hardware_clock += 1;
+        }
+    }
+
+    //called before each memory access of an instruction, so the DMA, the timers and the
+    //PPU catch up with it
+    fn bus_cycle(&mut self) {
+        //the timers and the PPU can write IF, which isn't an access of this instruction
+        if let Some(clock) = self.bus_clock.take() {
+            self.run_hardware(clock + 1);
+            self.bus_clock = Some(clock + 4);
+        }
+    }
+
+    //a cycle where the instruction doesn't touch memory, but the next access waits for it
+    pub fn internal_cycle(&mut self) {
+        self.bus_cycle();
+    }
+
     fn handle_timers(&mut self) {
         // increment DIV each 64 machine cycles.
         self.div_counter += 1;
@@ -295,67 +742,133 @@ impl<'a> CPU<'a> {
         }
     }
 
-    pub fn tick<W: std::io::Write>(
+    pub fn tick<D: SerialDevice + ?Sized>(
         &mut self,
         current_clock: u64,
         logger: &mut Option<Log>,
-        serial_out: &mut W,
+        serial: &mut D,
     ) {
-        self.handle_dma(current_clock);
-        self.handle_serial_transfer(serial_out);
+        self.handle_serial_transfer(serial);
+        self.run_hardware(current_clock + 1);
 
-        //only on CPU clocks
-        if current_clock % 4 == 0 {
-            self.handle_timers();
+        //not even interrupts get it out
+        if self.locked_up.is_some() {
+            return;
         }
 
         if current_clock >= self.next_clock {
-            if self.handle_interrupts() {
+            if self.handle_interrupts(current_clock) {
                 //skip the rest of the instruction, we'll continue after return
                 return;
             }
 
+            //the debugger might change the registers or memory before we run anything
+            if let Some(mut debugger) = self.debugger.take() {
+                debugger.before_instruction(self);
+                self.debugger = Some(debugger);
+                if self.should_exit {
+                    return;
+                }
+            }
+
+            self.instruction_PC = self.PC;
+
+            if !self.boot_mode {
+                if let Some(ref mut logger) = logger {
+                    logger.log_state(self).unwrap();
+                }
+            }
+
+            self.bus_clock = Some(current_clock);
+
             //handle cb
+            self.bus_cycle();
             let mut instr = self.peek_instruction() as u16;
             if instr == 0xcb {
                 self.PC += 1;
                 instr <<= 8;
+                self.bus_cycle();
                 instr |= self.peek_instruction() as u16;
             }
 
             if !self.boot_mode {
                 if let Some(ref mut logger) = logger {
                     let pc = self.PC as usize;
+                    let bank = self.rom_bank(self.PC);
                     logger
-                        .log_instruction(instr, &self.RAM[pc + 1..pc + 3], pc)
+                        .log_instruction(instr, &self.RAM[pc + 1..pc + 3], bank, pc)
                         .unwrap();
                 }
             }
 
+            //EI turns the interrupts on after the instruction that follows it
+            let enable_interrupts = self.interrupts_enable_pending;
+
             unsafe {
                 interpreter::interpret(instr, self);
             }
+            self.bus_clock = None;
 
-            //don't count the prefix itself
-            if self.interrupt_change_counter > 0 {
-                self.interrupt_change_counter -= 1;
-                if self.interrupt_change_counter == 0 {
-                    self.interrupts_master_enabled = self.interrupts_master_enabled_next;
+            //unless that was DI
+            if enable_interrupts && self.interrupts_enable_pending {
+                self.interrupts_master_enabled = true;
+                self.interrupts_enable_pending = false;
+            }
+
+            //a watchpoint asked to stop, let the debugger take over if there's one.
+            //otherwise the host polls take_pause itself
+            if let Some(ref mut debugger) = self.debugger {
+                if self.watchpoints.borrow_mut().take_pause() {
+                    debugger.pause();
                 }
             }
         }
     }
 
+    //what the interpreter runs for the opcodes that don't exist
+    pub fn lock_up(&mut self, opcode: u8) {
+        let lock_up = LockUp {
+            pc: self.instruction_PC,
+            opcode: opcode,
+        };
+        self.locked_up = Some(lock_up);
+        self.lock_up_event = Some(lock_up);
+    }
+
+    pub fn locked_up(&self) -> Option<LockUp> {
+        self.locked_up
+    }
+
+    //returns the lock up once, for the host to report it
+    pub fn take_lock_up(&mut self) -> Option<LockUp> {
+        self.lock_up_event.take()
+    }
+
     pub fn is_dma_mode(&self) -> bool {
         self.DMA_transfer.is_some()
     }
 
-    pub fn enable_interrupts(&mut self, future_state: bool) {
-        self.interrupt_change_counter = 2;
-        self.interrupts_master_enabled_next = match future_state {
-            true => u8::max_value(),
-            false => 0,
-        };
+    //EI
+    pub fn enable_interrupts(&mut self) {
+        if !self.interrupts_master_enabled {
+            self.interrupts_enable_pending = true;
+        }
+    }
+
+    //DI, right away
+    pub fn disable_interrupts(&mut self) {
+        self.interrupts_master_enabled = false;
+        self.interrupts_enable_pending = false;
+    }
+
+    //RETI, there's no delay like for EI
+    pub fn return_from_interrupt(&mut self) {
+        self.ret();
+        self.interrupts_master_enabled = true;
+    }
+
+    pub fn interrupts_enabled(&self) -> bool {
+        self.interrupts_master_enabled
     }
 
     fn request_interrupt_id(&mut self, idx: usize) {
@@ -366,6 +879,9 @@ impl<'a> CPU<'a> {
 
     pub fn request_vblank(&mut self) {
         self.request_interrupt_id(0);
+        let (ram, rom_controller) = (&mut self.RAM, &mut self.rom_controller);
+        self.cheats
+            .write_ram(|bank, addr, val| rom_controller.poke(bank, addr, val, &mut ram[..]));
     }
 
     pub fn request_timer_interrupt(&mut self) {
@@ -376,35 +892,103 @@ impl<'a> CPU<'a> {
         self.request_interrupt_id(3);
     }
 
+    fn request_joypad_interrupt(&mut self) {
+        self.request_interrupt_id(4);
+    }
+
+    //the pressed buttons, see joypad for the bits
+    pub fn set_buttons(&mut self, pressed: u8) {
+        self.buttons = pressed;
+
+        let old = self.RAM[address::P1_REGISTER];
+        let new = joypad::p1_register(old, pressed);
+        self.RAM[address::P1_REGISTER] = new;
+
+        //the interrupt fires when a selected line goes low
+        if old & !new & 0x0f != 0 {
+            self.request_joypad_interrupt();
+        }
+    }
+
+    pub fn buttons(&self) -> u8 {
+        self.buttons
+    }
+
     pub fn peek_instruction(&self) -> u8 {
-        self.address(self.PC)
+        self.read(self.PC, coverage::OPCODE)
     }
 
-    pub fn immediate_u16(&self) -> u16 {
+    pub fn immediate_u16(&mut self) -> u16 {
         //assuming that the PC is at the start of the instruction
-        let lo = self.address(self.PC + 1) as u16;
-        let hi = self.address(self.PC + 2) as u16;
+        self.bus_cycle();
+        let lo = self.read(self.PC + 1, coverage::OPERAND) as u16;
+        self.bus_cycle();
+        let hi = self.read(self.PC + 2, coverage::OPERAND) as u16;
 
         (hi << 8) | lo
     }
-    pub fn immediate_u8(&self) -> u8 {
+    pub fn immediate_u8(&mut self) -> u8 {
         //assuming that the PC is at the start of the instruction
-        self.address(self.PC + 1)
+        self.bus_cycle();
+        self.read(self.PC + 1, coverage::OPERAND)
     }
-    pub fn immediate_i8(&self) -> i8 {
+    pub fn immediate_i8(&mut self) -> i8 {
         //assuming that the PC is at the start of the instruction
-        unsafe { std::mem::transmute::<u8, i8>(self.address(self.PC + 1)) }
+        self.bus_cycle();
+        unsafe { std::mem::transmute::<u8, i8>(self.read(self.PC + 1, coverage::OPERAND)) }
     }
 
     pub fn run_cycles(&mut self, count: u64) {
         self.next_clock += count;
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.add_cycles(count);
+        }
     }
 
-    pub fn address(&self, addr: u16) -> u8 {
-        let addr = addr as usize;
-        address::check_unimplemented_read(addr);
+    pub fn address(&mut self, addr: u16) -> u8 {
+        self.bus_cycle();
+        self.read(addr, coverage::DATA)
+    }
+
+    //usage is what the byte is read as, for the coverage map
+    fn read(&self, addr: u16, usage: u8) -> u8 {
+        if let Some(ref coverage) = self.coverage {
+            if let Some(offset) = self.rom_offset(addr) {
+                coverage.borrow_mut().mark(offset, usage);
+            }
+        }
+
+        let mut val = self.RAM[addr as usize];
+        if !self.flat_ram {
+            address::check_unimplemented_read(addr as usize);
 
-        self.RAM[addr]
+            //disabled cartridge RAM reads as an open bus
+            if address::in_range(address::EXTERNAL_RAM, addr as usize)
+                && !self.rom_controller.ram_readable()
+            {
+                val = 0xff;
+            }
+        }
+
+        if !self.cheats.is_empty() && self.rom_offset(addr).is_some() {
+            val = self.cheats.patch_rom(addr, val);
+        }
+
+        self.check_watchpoints(addr, val, val, Access::Read);
+        val
+    }
+
+    fn check_watchpoints(&self, addr: u16, old: u8, new: u8, access: Access) {
+        let mut watchpoints = self.watchpoints.borrow_mut();
+        if !watchpoints.is_empty() {
+            watchpoints.check(WatchHit {
+                pc: self.instruction_PC,
+                addr: addr,
+                old: old,
+                new: new,
+                access: access,
+            });
+        }
     }
 
     fn handle_rom_controller(&mut self, addr: usize, val: u8) -> bool {
@@ -416,17 +1000,37 @@ impl<'a> CPU<'a> {
                 }
                 return false;
             }
-            ROMController::MBC1(ref mut mbc) => return mbc.handle_write(addr, val, &mut self.RAM),
+            ROMController::MBC1(ref mut mbc) => {
+                return mbc.handle_write(addr, val, self.cartridge_ROM, &mut self.RAM)
+            }
         }
     }
 
-    fn start_serial_transfer(&mut self, _val: u8) {
-        //TODO setup the clocks and the other parameters...
-        //for now handle() will just print out the register when anything is there
+    fn start_serial_transfer(&mut self, val: u8) {
+        self.serial_transfer = if val.get_bit(7) {
+            Some(SerialTransfer::from_reg(val))
+        } else {
+            None
+        };
+    }
+
+    pub fn set_address(&mut self, addr: u16, val: u8) {
+        self.bus_cycle();
+        let old = self.RAM[addr as usize];
+        self.write_address(addr, val);
+
+        //report what actually ended up in memory, eg. writes to ROM don't change anything
+        let new = self.RAM[addr as usize];
+        self.check_watchpoints(addr, old, new, Access::Write);
     }
 
-    pub fn set_address(&mut self, addr: u16, mut val: u8) {
+    fn write_address(&mut self, addr: u16, mut val: u8) {
         let addr = addr as usize;
+        if self.flat_ram {
+            self.RAM[addr] = val;
+            return;
+        }
+
         //TODO how to not check this for every set ever?
         if self.boot_mode && addr == address::INTERNAL_ROM_TURN_OFF {
             //replace the Nintendo boot ROM with the first 256 bytes of the cart
@@ -436,6 +1040,9 @@ impl<'a> CPU<'a> {
             self.DMA_transfer = Some(DMATransfer::from_reg(val));
         } else if addr == address::SC_REGISTER {
             self.start_serial_transfer(val);
+        } else if addr == address::P1_REGISTER {
+            //only the select bits can be written
+            val = joypad::p1_register(val, self.buttons);
         } else if address::in_range(address::ECHO_MEM, addr) {
             let echo_addr = (addr - address::ECHO_MEM.start) + address::ECHO_MEM_TARGET.start;
             self.RAM[echo_addr] = val;
@@ -472,6 +1079,8 @@ impl<'a> CPU<'a> {
         let hi = (val >> 8) as u8;
         let lo = val as u8;
 
+        //SP goes down on a cycle of its own
+        self.internal_cycle();
         sp = sp.wrapping_sub(1);
         self.set_address(sp, hi);
         sp = sp.wrapping_sub(1);
@@ -491,7 +1100,29 @@ impl<'a> CPU<'a> {
     pub fn call(&mut self, addr: u16) {
         let pc = self.PC;
         self.push16(pc);
+        self.jump_to_routine(addr);
+    }
+
+    //the PC was pushed already
+    fn jump_to_routine(&mut self, addr: u16) {
         self.PC = addr;
+
+        let routine = Routine {
+            bank: self.rom_bank(addr),
+            addr: addr,
+        };
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.enter(routine, self.instruction_PC, self.SP);
+        }
+    }
+
+    pub fn ret(&mut self) {
+        let sp = self.SP;
+        self.PC = self.pop16();
+
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.exit(sp);
+        }
     }
 
     pub unsafe fn set_z(&mut self, val: bool) {
@@ -1,8 +1,8 @@
 extern crate std;
 
-extern crate serde;
-extern crate serde_json;
-
+use cpu::CPU;
+use disasm;
+use symbols::Symbols;
 use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::prelude::*;
@@ -11,104 +11,71 @@ use std::io::Write;
 use std::time::Duration;
 use std::time::Instant;
 
-#[derive(Deserialize, Debug)]
-#[allow(non_snake_case)]
-struct OpCodeDesc {
-    mnemonic: String,
-    operands: Vec<String>,
-    bytes: usize,
-    cycles: usize,
-    flagsZNHC: Vec<String>,
-}
-
-impl OpCodeDesc {
-    fn shorthand(&self, immediate: &[u8], pc: usize) -> String {
-        let mut line = String::new();
-        let mnemonic = &self.mnemonic;
-        line += mnemonic;
-
-        for op in &self.operands {
-            let mut op = op.clone();
-
-            if op.contains("inout ") {
-                op = op.replace("inout ", "");
-            } else if op.contains("out ") {
-                op = op.replace("out ", "");
-            }
-            if op.contains("d8") {
-                op = op.replace("d8", &format!("0x{:02x}", immediate[0]));
-            }
-            if op.contains("d16") {
-                op = op.replace(
-                    "d16",
-                    &format!(
-                        "0x{:04x}",
-                        ((immediate[1] as u16) << 8) | (immediate[0] as u16)
-                    ),
-                );
-            } else if op.contains("a16") {
-                op = op.replace(
-                    "a16",
-                    &format!(
-                        "0x{:04x}",
-                        ((immediate[1] as u16) << 8) | (immediate[0] as u16)
-                    ),
-                );
-            }
-            if op.contains("r8") {
-                op = op.replace(
-                    "r8",
-                    &format!("0x{:04x}", unsafe {
-                        std::mem::transmute::<u8, i8>(immediate[0]) as i32 + pc as i32
-                    }),
-                );
-            }
-            if op.contains("a8") {
-                op = op.replace("a8", &format!("0x{:04x}", immediate[0] as usize + 0xff00));
-            }
-
-            line += " ";
-            line += &op;
-        }
-
-        line
-    }
-}
-
 pub struct Log {
-    disasm_file: File,
-    opcodes: BTreeMap<String, OpCodeDesc>,
+    //the map of the code that ran so far, rewritten every second
+    disasm_file: Option<File>,
     disassembly: BTreeMap<usize, (String, usize)>,
     next_write_time: Instant,
+    symbols: Option<Symbols>,
+
+    //the CPU state before every instruction, one line each
+    trace: Option<Box<dyn Write>>,
 }
 
 impl Drop for Log {
     fn drop(&mut self) {
-        self.write_to_file().unwrap(); //can't return an error here
+        //can't return an error here
+        self.write_to_file().unwrap();
+        if let Some(ref mut trace) = self.trace {
+            trace.flush().unwrap();
+        }
     }
 }
 
 impl Log {
     pub fn new() -> Self {
         Log {
-            disasm_file: File::create("disasm_file.txt").unwrap(),
-            opcodes: serde_json::from_reader(File::open("opcodes.json").unwrap()).unwrap(),
+            disasm_file: Some(File::create("disasm_file.txt").unwrap()),
             disassembly: BTreeMap::new(),
             next_write_time: Instant::now(),
+            symbols: None,
+            trace: None,
         }
     }
 
+    //traces in the gameboy-doctor format, to diff against the logs of other emulators:
+    //A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+    pub fn trace(out: Box<dyn Write>) -> Self {
+        Log {
+            disasm_file: None,
+            disassembly: BTreeMap::new(),
+            next_write_time: Instant::now(),
+            symbols: None,
+            trace: Some(out),
+        }
+    }
+
+    //labels the disassembly
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = Some(symbols);
+    }
+
     fn write_to_file(&mut self) -> std::io::Result<()> {
-        self.disasm_file.seek(SeekFrom::Start(0))?;
+        let disasm_file = match self.disasm_file {
+            Some(ref mut file) => file,
+            None => return Ok(()),
+        };
+
+        disasm_file.seek(SeekFrom::Start(0))?;
         let mut last_addr = 0;
         for (addr, (text, _)) in &self.disassembly {
             if *addr > last_addr + 4 {
-                writeln!(self.disasm_file, "")?;
-                writeln!(self.disasm_file, "...")?;
-                writeln!(self.disasm_file, "")?;
+                writeln!(disasm_file, "")?;
+                writeln!(disasm_file, "...")?;
+                writeln!(disasm_file, "")?;
             }
 
-            writeln!(self.disasm_file, "{}", text)?;
+            writeln!(disasm_file, "{}", text)?;
             last_addr = *addr;
         }
 
@@ -120,8 +87,13 @@ impl Log {
         &mut self,
         instr: u16,
         immediate: &[u8],
+        bank: usize,
         pc: usize,
     ) -> std::io::Result<()> {
+        if self.disasm_file.is_none() {
+            return Ok(());
+        }
+
         //compose the line
         {
             let mut count = 0;
@@ -129,12 +101,29 @@ impl Log {
                 count = c + 1;
             }
 
-            let mut line = format!("{:04x}\t({:05})\t", pc, count);
-            let text_instruction = format!("0x{:02x}", instr);
-
-            let opcode = &self.opcodes[&text_instruction];
+            let mut line = String::new();
+            let symbols = self.symbols.as_ref();
+            if let Some(label) = symbols.and_then(|symbols| symbols.label(bank, pc as u16)) {
+                line += &format!("{}:\n", label);
+            }
 
-            line += &opcode.shorthand(immediate, pc + opcode.bytes);
+            line += &format!("{:04x}\t({:05})\t", pc, count);
+            line += &match disasm::opcode(instr) {
+                Some(opcode) => opcode.shorthand(immediate, pc + opcode.bytes),
+                None => format!("DB 0x{:02x}", instr),
+            };
+
+            //name where jumps and calls go
+            if let Some(symbols) = symbols.filter(|_| instr < 0x100) {
+                let bytes = [instr as u8, immediate[0], immediate[1]];
+                let instruction = disasm::decode(&bytes, bank, pc as u16);
+                let label = instruction.target().and_then(|target| {
+                    symbols.label(instruction.target_bank(target), target)
+                });
+                if let Some(label) = label {
+                    line += &format!("\t; {}", label);
+                }
+            }
 
             self.disassembly.insert(pc, (line, count));
         }
@@ -144,4 +133,36 @@ impl Log {
 
         Ok(())
     }
+
+    //called before the instruction at the PC runs
+    pub fn log_state(&mut self, cpu: &CPU) -> std::io::Result<()> {
+        let trace = match self.trace {
+            Some(ref mut trace) => trace,
+            None => return Ok(()),
+        };
+
+        let pc = cpu.PC as usize;
+        let pcmem = |offset: usize| cpu.RAM[(pc + offset) % cpu.RAM.len()];
+        unsafe {
+            writeln!(
+                trace,
+                "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+                 SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+                cpu.AF.r8.first,
+                cpu.AF.r8.second,
+                cpu.BC.r8.first,
+                cpu.BC.r8.second,
+                cpu.DE.r8.first,
+                cpu.DE.r8.second,
+                cpu.HL.r8.first,
+                cpu.HL.r8.second,
+                cpu.SP,
+                cpu.PC,
+                pcmem(0),
+                pcmem(1),
+                pcmem(2),
+                pcmem(3)
+            )
+        }
+    }
 }
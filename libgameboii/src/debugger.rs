@@ -0,0 +1,550 @@
+extern crate std;
+
+use cpu::CPU;
+use disasm;
+use disasm::Instruction;
+use ram_search::{Filter, RamSearch};
+use std::io;
+use std::io::{BufRead, Write};
+use symbols::Symbols;
+
+const HELP: &str = "\
+addresses and values are hex, counts are decimal. With symbols, labels work as addresses
+  break [BANK:]ADDR   b   stop before running ADDR, in BANK if given
+  delete N                remove breakpoint N
+  breakpoints         bl  list the breakpoints
+  step [COUNT]        s   run COUNT instructions
+  next                n   like step, but runs over CALL and RST
+  finish              f   run until the current function returns
+  backtrace           bt  show the calls that led here
+  continue            c   run until a breakpoint
+  regs                r   show the registers
+  set REG VALUE           change a register: a f b c d e h l af bc de hl sp pc
+  x ADDR [COUNT]          show COUNT bytes of memory
+  write ADDR VALUE..  w   write bytes to memory
+  disasm [COUNT]      d   disassemble around the PC
+  search                  snapshot RAM to look for a variable in
+  search eq|ne|inc|dec    keep the bytes that stayed the same, changed, went up or down
+  search VALUE            keep the bytes that are VALUE now
+  search list             show what's left
+  cheats                  list the cheats
+  cheat N                 turn cheat N on or off
+  cheat add CODE [NAME]   add a Game Genie or GameShark code
+  quit                q   stop the emulator";
+
+//instructions that push a return address
+const CALLS: [u8; 5] = [0xcd, 0xc4, 0xcc, 0xd4, 0xdc];
+
+const MAX_BACKTRACE_FRAMES: usize = 32;
+const MAX_SEARCH_RESULTS: usize = 32;
+
+fn parse_hex(text: &str) -> Option<u16> {
+    let digits = text.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn is_call(opcode: u8) -> bool {
+    //RST is 0xc7, 0xcf ... 0xff
+    CALLS.contains(&opcode) || opcode & 0xc7 == 0xc7
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Breakpoint {
+    bank: Option<usize>,
+    addr: u16,
+}
+
+impl Breakpoint {
+    fn parse(text: &str, symbols: Option<&Symbols>) -> Option<Self> {
+        if let Some((bank, addr)) = symbols.and_then(|symbols| symbols.lookup(text)) {
+            //only the switchable area cares about the bank
+            let banked = addr >= 0x4000 && addr < 0x8000;
+            return Some(Breakpoint {
+                bank: if banked { Some(bank) } else { None },
+                addr: addr,
+            });
+        }
+
+        let mut parts = text.splitn(2, ':');
+        let first = parts.next()?;
+        match parts.next() {
+            Some(addr) => Some(Breakpoint {
+                bank: Some(parse_hex(first)? as usize),
+                addr: parse_hex(addr)?,
+            }),
+            None => Some(Breakpoint {
+                bank: None,
+                addr: parse_hex(first)?,
+            }),
+        }
+    }
+
+    fn hit(&self, cpu: &CPU) -> bool {
+        let bank_matches = match self.bank {
+            Some(bank) => bank == cpu.rom_bank(self.addr),
+            None => true,
+        };
+        bank_matches && cpu.PC == self.addr
+    }
+}
+
+enum RunMode {
+    Continue,
+    //stop when the count gets to 0
+    Step(u32),
+    //stop when we get back to this address, on the same stack frame
+    Until { addr: u16, sp: u16 },
+    //stop when the stack is above this, ie. after a return
+    Finish { sp: u16 },
+}
+
+//anything that wants to look at the CPU between instructions
+pub trait DebugHook {
+    //called before each instruction runs
+    fn before_instruction(&mut self, cpu: &mut CPU);
+
+    //stop before the next instruction
+    fn pause(&mut self);
+}
+
+//an interactive debugger. The CPU hands control to it before each instruction,
+//and it stops to read commands when it hits a breakpoint or finishes a step
+pub struct Debugger {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+
+    symbols: Option<Symbols>,
+    breakpoints: Vec<Breakpoint>,
+    search: Option<RamSearch>,
+    mode: RunMode,
+    last_command: String,
+    //the terminal went away, the game keeps running without it
+    detached: bool,
+}
+
+impl Debugger {
+    //starts stopped, before the first instruction
+    pub fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Debugger {
+            input: input,
+            output: output,
+            symbols: None,
+            breakpoints: vec![],
+            search: None,
+            mode: RunMode::Step(1),
+            last_command: String::new(),
+            detached: false,
+        }
+    }
+
+    pub fn stdio() -> Self {
+        let stdin = io::BufReader::new(io::stdin());
+        Self::new(Box::new(stdin), Box::new(io::stdout()))
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = Some(symbols);
+    }
+
+    pub fn add_breakpoint(&mut self, bank: Option<usize>, addr: u16) {
+        self.breakpoints.push(Breakpoint {
+            bank: bank,
+            addr: addr,
+        });
+    }
+
+    fn should_stop(&mut self, cpu: &CPU) -> bool {
+        let done = match self.mode {
+            RunMode::Continue => false,
+            RunMode::Step(ref mut count) => {
+                *count -= 1;
+                *count == 0
+            }
+            RunMode::Until { addr, sp } => cpu.PC == addr && cpu.SP == sp,
+            RunMode::Finish { sp } => cpu.SP > sp,
+        };
+
+        done || self.breakpoints.iter().any(|b| b.hit(cpu))
+    }
+
+    fn repl(&mut self, cpu: &mut CPU) -> io::Result<()> {
+        self.print_location(cpu)?;
+
+        loop {
+            write!(self.output, "(gbdb) ")?;
+            self.output.flush()?;
+
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                //nobody is going to type anything else
+                cpu.should_exit = true;
+                return Ok(());
+            }
+
+            //an empty line repeats the last command, like gdb
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_owned(),
+            };
+            self.last_command = line.clone();
+
+            let args = line.split_whitespace().collect::<Vec<_>>();
+            if args.is_empty() {
+                continue;
+            }
+
+            if self.run_command(cpu, &args)? {
+                return Ok(());
+            }
+        }
+    }
+
+    //returns true when the CPU should start running again
+    fn run_command(&mut self, cpu: &mut CPU, args: &[&str]) -> io::Result<bool> {
+        match args[0] {
+            "break" | "b" => match args
+                .get(1)
+                .and_then(|arg| Breakpoint::parse(arg, self.symbols.as_ref()))
+            {
+                Some(breakpoint) => {
+                    self.breakpoints.push(breakpoint);
+                    writeln!(self.output, "breakpoint {}", self.breakpoints.len() - 1)?;
+                }
+                None => writeln!(self.output, "usage: break [BANK:]ADDR or break LABEL")?,
+            },
+            "delete" => match args.get(1).and_then(|arg| arg.parse::<usize>().ok()) {
+                Some(idx) if idx < self.breakpoints.len() => {
+                    self.breakpoints.remove(idx);
+                }
+                _ => writeln!(self.output, "no such breakpoint")?,
+            },
+            "breakpoints" | "bl" => {
+                for (idx, breakpoint) in self.breakpoints.iter().enumerate() {
+                    let label = self.label(breakpoint.bank.unwrap_or(0), breakpoint.addr);
+                    match breakpoint.bank {
+                        Some(bank) => writeln!(
+                            self.output,
+                            "{}: {:02x}:{:04x}{}",
+                            idx, bank, breakpoint.addr, label
+                        )?,
+                        None => writeln!(self.output, "{}: {:04x}{}", idx, breakpoint.addr, label)?,
+                    }
+                }
+            }
+            "backtrace" | "bt" => self.backtrace(cpu)?,
+            "step" | "s" => {
+                let count = args.get(1).and_then(|arg| arg.parse().ok()).unwrap_or(1);
+                self.mode = RunMode::Step(std::cmp::max(count, 1));
+                return Ok(true);
+            }
+            "next" | "n" => {
+                let opcode = cpu.RAM[cpu.PC as usize];
+                self.mode = if is_call(opcode) {
+                    RunMode::Until {
+                        addr: cpu.PC.wrapping_add(self.instruction_size(cpu, cpu.PC) as u16),
+                        sp: cpu.SP,
+                    }
+                } else {
+                    RunMode::Step(1)
+                };
+                return Ok(true);
+            }
+            "finish" | "f" => {
+                self.mode = RunMode::Finish { sp: cpu.SP };
+                return Ok(true);
+            }
+            "continue" | "c" => {
+                self.mode = RunMode::Continue;
+                return Ok(true);
+            }
+            "regs" | "r" => self.print_registers(cpu)?,
+            "set" => {
+                let value = args.get(2).and_then(|arg| parse_hex(arg));
+                match (args.get(1), value) {
+                    (Some(reg), Some(value)) => {
+                        if !set_register(cpu, reg, value) {
+                            writeln!(self.output, "unknown register {}", reg)?;
+                        }
+                    }
+                    _ => writeln!(self.output, "usage: set REG VALUE")?,
+                }
+            }
+            "x" => match args.get(1).and_then(|arg| parse_hex(arg)) {
+                Some(addr) => {
+                    let count = args.get(2).and_then(|arg| arg.parse().ok()).unwrap_or(16);
+                    self.examine(cpu, addr, count)?;
+                }
+                None => writeln!(self.output, "usage: x ADDR [COUNT]")?,
+            },
+            "write" | "w" => {
+                let values = args[1..]
+                    .iter()
+                    .map(|arg| parse_hex(arg))
+                    .collect::<Option<Vec<_>>>();
+                match values {
+                    Some(ref values) if values.len() >= 2 => {
+                        let addr = values[0];
+                        for (offset, value) in values[1..].iter().enumerate() {
+                            cpu.RAM[addr.wrapping_add(offset as u16) as usize] = *value as u8;
+                        }
+                    }
+                    _ => writeln!(self.output, "usage: write ADDR VALUE..")?,
+                }
+            }
+            "disasm" | "d" => {
+                let count = args.get(1).and_then(|arg| arg.parse().ok()).unwrap_or(10);
+                self.disassemble_around(cpu, count)?;
+            }
+            "search" => self.search(cpu, args.get(1).cloned())?,
+            "cheats" => {
+                for (idx, cheat) in cpu.cheats.list().iter().enumerate() {
+                    let state = if cheat.enabled { "on " } else { "off" };
+                    writeln!(self.output, "{}: {} {}  {}", idx, state, cheat.code, cheat.name)?;
+                }
+            }
+            "cheat" => match (args.get(1), args.get(2)) {
+                (Some(&"add"), Some(code)) => {
+                    let name = args[3..].join(" ");
+                    if let Err(error) = cpu.cheats.add(code, &name) {
+                        writeln!(self.output, "{}", error)?;
+                    }
+                }
+                (Some(idx), None) => match idx.parse().ok().and_then(|idx| cpu.cheats.toggle(idx)) {
+                    Some(true) => writeln!(self.output, "cheat {} on", idx)?,
+                    Some(false) => writeln!(self.output, "cheat {} off", idx)?,
+                    None => writeln!(self.output, "no such cheat")?,
+                },
+                _ => writeln!(self.output, "usage: cheat N or cheat add CODE [NAME]")?,
+            },
+            "quit" | "q" => {
+                cpu.should_exit = true;
+                return Ok(true);
+            }
+            "help" | "h" => writeln!(self.output, "{}", HELP)?,
+            other => writeln!(self.output, "unknown command {}, try help", other)?,
+        }
+        Ok(false)
+    }
+
+    fn instruction_size(&self, cpu: &CPU, addr: u16) -> usize {
+        decode(cpu, addr).size()
+    }
+
+    //" <Label+offset>" when there are symbols for the address
+    fn label(&self, bank: usize, addr: u16) -> String {
+        let label = self
+            .symbols
+            .as_ref()
+            .and_then(|symbols| symbols.describe(bank, addr));
+        match label {
+            Some(label) => format!(" <{}>", label),
+            None => String::new(),
+        }
+    }
+
+    fn print_location(&mut self, cpu: &CPU) -> io::Result<()> {
+        let instruction = decode(cpu, cpu.PC);
+        writeln!(
+            self.output,
+            "{:02x}:{:04x}{}  {}",
+            instruction.bank,
+            instruction.addr,
+            self.label(instruction.bank, instruction.addr),
+            instruction.text()
+        )
+    }
+
+    //the profiler keeps track of the calls. Without it there are no frame pointers, so
+    //look for return addresses on the stack: values that point right after a CALL or RST.
+    //Data can look like one too
+    fn backtrace(&mut self, cpu: &CPU) -> io::Result<()> {
+        let mut frames = vec![cpu.PC];
+        if let Some(ref profiler) = cpu.profiler {
+            let calls = profiler.call_stack().iter().rev();
+            frames.extend(calls.map(|frame| frame.call_site));
+        } else {
+            let mut sp = cpu.SP as usize;
+            while sp + 1 < cpu.RAM.len() && frames.len() < MAX_BACKTRACE_FRAMES {
+                let ret = cpu.RAM[sp] as u16 | (cpu.RAM[sp + 1] as u16) << 8;
+                if let Some(call) = call_site(cpu, ret) {
+                    frames.push(call);
+                }
+                sp += 2;
+            }
+        }
+
+        for (idx, addr) in frames.into_iter().enumerate() {
+            let bank = cpu.rom_bank(addr);
+            let label = self.label(bank, addr);
+            writeln!(self.output, "#{:<2} {:02x}:{:04x}{}", idx, bank, addr, label)?;
+        }
+        Ok(())
+    }
+
+    fn print_registers(&mut self, cpu: &CPU) -> io::Result<()> {
+        unsafe {
+            writeln!(
+                self.output,
+                "AF {:04x}  BC {:04x}  DE {:04x}  HL {:04x}  SP {:04x}  PC {:04x}  [{}{}{}{}]",
+                cpu.AF.r16,
+                cpu.BC.r16,
+                cpu.DE.r16,
+                cpu.HL.r16,
+                cpu.SP,
+                cpu.PC,
+                if cpu.z() { 'Z' } else { '-' },
+                if cpu.n() { 'N' } else { '-' },
+                if cpu.h() { 'H' } else { '-' },
+                if cpu.c() { 'C' } else { '-' },
+            )
+        }
+    }
+
+    fn examine(&mut self, cpu: &CPU, addr: u16, count: usize) -> io::Result<()> {
+        for line_start in (0..count).step_by(16) {
+            let line_addr = addr.wrapping_add(line_start as u16);
+            write!(self.output, "{:04x}:", line_addr)?;
+            for offset in line_start..std::cmp::min(line_start + 16, count) {
+                let byte = cpu.RAM[addr.wrapping_add(offset as u16) as usize];
+                write!(self.output, " {:02x}", byte)?;
+            }
+            writeln!(self.output)?;
+        }
+        Ok(())
+    }
+
+    fn search(&mut self, cpu: &CPU, arg: Option<&str>) -> io::Result<()> {
+        let filter = match arg {
+            None | Some("start") => {
+                self.search = Some(RamSearch::new(&cpu.RAM));
+                None
+            }
+            Some("list") => None,
+            Some("eq") => Some(Filter::Equal),
+            Some("ne") => Some(Filter::Changed),
+            Some("inc") => Some(Filter::Increased),
+            Some("dec") => Some(Filter::Decreased),
+            Some(value) => match parse_hex(value) {
+                Some(value) if value <= 0xff => Some(Filter::Value(value as u8)),
+                _ => return writeln!(self.output, "usage: search [eq|ne|inc|dec|VALUE|list]"),
+            },
+        };
+
+        let search = match self.search {
+            Some(ref mut search) => search,
+            None => return writeln!(self.output, "no search started, use search first"),
+        };
+        if let Some(filter) = filter {
+            search.filter(&cpu.RAM, filter);
+        }
+
+        let candidates = search.candidates();
+        writeln!(self.output, "{} candidates", candidates.len())?;
+        if arg == Some("list") {
+            for candidate in candidates.iter().take(MAX_SEARCH_RESULTS) {
+                writeln!(self.output, "{:04x}: {:02x}", candidate.addr, candidate.value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn disassemble_around(&mut self, cpu: &CPU, count: usize) -> io::Result<()> {
+        //instructions have different sizes, so look for an earlier start that lines up with the PC
+        let before = count / 3;
+        let pc = cpu.PC as usize;
+        let mut start = pc;
+        for back in (1..before * 3 + 1).rev() {
+            let candidate = pc.saturating_sub(back);
+            let mut addr = candidate;
+            let mut lines = 0;
+            while addr < pc {
+                addr += decode(cpu, addr as u16).size();
+                lines += 1;
+            }
+            if addr == pc && lines <= before {
+                start = candidate;
+                break;
+            }
+        }
+
+        let mut addr = start;
+        for _ in 0..count {
+            if addr >= cpu.RAM.len() {
+                break;
+            }
+            let instruction = decode(cpu, addr as u16);
+            let marker = if addr == pc { "=>" } else { "  " };
+            writeln!(self.output, "{} {:04x}  {}", marker, addr, instruction.text())?;
+            addr += instruction.size();
+        }
+        Ok(())
+    }
+}
+
+impl DebugHook for Debugger {
+    fn before_instruction(&mut self, cpu: &mut CPU) {
+        if self.detached || !self.should_stop(cpu) {
+            return;
+        }
+
+        self.mode = RunMode::Continue;
+        if let Err(error) = self.repl(cpu) {
+            //stdout might be what failed
+            eprintln!("The debugger stopped working");
+            eprintln!("{}", error);
+            self.detached = true;
+        }
+    }
+
+    fn pause(&mut self) {
+        if !self.detached {
+            self.mode = RunMode::Step(1);
+        }
+    }
+}
+
+//where the call that returns to ret is, if it looks like there's one
+fn call_site(cpu: &CPU, ret: u16) -> Option<u16> {
+    //code runs from ROM, or from HRAM for the DMA routine
+    if ret >= 0x8000 && ret < 0xff80 {
+        return None;
+    }
+
+    let byte = |addr: u16| cpu.RAM[addr as usize];
+    if ret >= 3 && CALLS.contains(&byte(ret - 3)) {
+        Some(ret - 3)
+    } else if ret >= 1 && byte(ret - 1) & 0xc7 == 0xc7 && byte(ret - 1) != 0xff {
+        //an RST. RST 38 is also what empty ROM looks like, so it's left out
+        Some(ret - 1)
+    } else {
+        None
+    }
+}
+
+fn decode(cpu: &CPU, addr: u16) -> Instruction {
+    disasm::decode(&cpu.RAM[addr as usize..], cpu.rom_bank(addr), addr)
+}
+
+//returns false if the register doesn't exist
+pub fn set_register(cpu: &mut CPU, name: &str, value: u16) -> bool {
+    let byte = value as u8;
+    match name.to_lowercase().as_str() {
+        "a" => cpu.AF.r8.first = byte,
+        //the low bits of F always read 0
+        "f" => cpu.AF.r8.second = byte & 0xf0,
+        "b" => cpu.BC.r8.first = byte,
+        "c" => cpu.BC.r8.second = byte,
+        "d" => cpu.DE.r8.first = byte,
+        "e" => cpu.DE.r8.second = byte,
+        "h" => cpu.HL.r8.first = byte,
+        "l" => cpu.HL.r8.second = byte,
+        "af" => cpu.AF.r16 = value & 0xfff0,
+        "bc" => cpu.BC.r16 = value,
+        "de" => cpu.DE.r16 = value,
+        "hl" => cpu.HL.r16 = value,
+        "sp" => cpu.SP = value,
+        "pc" => cpu.PC = value,
+        _ => return false,
+    }
+    true
+}
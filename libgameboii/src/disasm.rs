@@ -0,0 +1,299 @@
+extern crate std;
+
+use opcode_table::{CB_OPCODES, OPCODES};
+use symbols::Symbols;
+use std::io;
+use std::io::Write;
+
+pub const BANK_SIZE: usize = 0x4000;
+
+//an entry of opcodes.json, built into the library
+pub struct OpCode {
+    pub mnemonic: &'static str,
+    pub operands: &'static [&'static str],
+    pub bytes: usize,
+    pub cycles: usize,
+    //for conditional branches, cycles is when they're taken
+    pub cycles_not_taken: Option<usize>,
+    pub flags: [&'static str; 4],
+}
+
+pub fn opcode(instr: u16) -> Option<&'static OpCode> {
+    if instr >> 8 == 0xcb {
+        CB_OPCODES[(instr & 0xff) as usize].as_ref()
+    } else if instr < 0x100 {
+        OPCODES[instr as usize].as_ref()
+    } else {
+        None
+    }
+}
+
+fn operand_name(op: &str) -> &str {
+    op.trim_start_matches("inout ").trim_start_matches("out ")
+}
+
+impl OpCode {
+    //the short form used by the debug log and the debugger, eg. "JR 0x0150".
+    //pc is the address after the instruction
+    pub fn shorthand(&self, immediate: &[u8], pc: usize) -> String {
+        let mut line = String::from(self.mnemonic);
+
+        for op in self.operands {
+            let mut op = operand_name(op).to_owned();
+
+            if op.contains("d8") {
+                op = op.replace("d8", &format!("0x{:02x}", immediate[0]));
+            }
+            if op.contains("d16") {
+                op = op.replace("d16", &format!("0x{:04x}", immediate16(immediate)));
+            } else if op.contains("a16") {
+                op = op.replace("a16", &format!("0x{:04x}", immediate16(immediate)));
+            }
+            if op.contains("r8") {
+                op = op.replace(
+                    "r8",
+                    &format!("0x{:04x}", immediate[0] as i8 as i32 + pc as i32),
+                );
+            }
+            if op.contains("a8") {
+                op = op.replace("a8", &format!("0x{:04x}", immediate[0] as usize + 0xff00));
+            }
+
+            line += " ";
+            line += &op;
+        }
+
+        line
+    }
+
+    //RGBDS syntax, eg. "jr $0150". pc is the address after the instruction
+    pub fn rgbds(&self, immediate: &[u8], pc: usize) -> String {
+        let mut mnemonic = self.mnemonic.to_lowercase();
+        let mut operands = vec![];
+
+        for op in self.operands {
+            let op = operand_name(op);
+            let text = match op {
+                "d8" => format!("${:02x}", immediate[0]),
+                "d16" | "a16" => format!("${:04x}", immediate16(immediate)),
+                "(a16)" => format!("[${:04x}]", immediate16(immediate)),
+                "(a8)" => format!("[${:04x}]", immediate[0] as usize + 0xff00),
+                //LD A,(C) is spelled ldh in RGBDS
+                "(C)" => {
+                    mnemonic = String::from("ldh");
+                    String::from("[c]")
+                }
+                //the offset of ADD SP,r8 is signed, JR takes the target
+                "r8" if self.mnemonic == "ADD" => format!("{}", immediate[0] as i8),
+                "r8" => format!("${:04x}", (immediate[0] as i8 as i32 + pc as i32) as u16),
+                "SP+r8" => format!("sp{:+}", immediate[0] as i8),
+                //STOP's padding byte is implied
+                "0" if self.mnemonic == "STOP" => continue,
+                op if op.ends_with('H') && op.len() == 3 => format!("${}", &op[..2]),
+                op => op.replace('(', "[").replace(')', "]").to_lowercase(),
+            };
+            operands.push(text);
+        }
+
+        if operands.is_empty() {
+            mnemonic
+        } else {
+            format!("{} {}", mnemonic, operands.join(", "))
+        }
+    }
+}
+
+fn immediate16(immediate: &[u8]) -> u16 {
+    ((immediate[1] as u16) << 8) | (immediate[0] as u16)
+}
+
+//one decoded instruction, or a byte that isn't one
+pub struct Instruction {
+    pub bank: usize,
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub opcode: Option<&'static OpCode>,
+}
+
+impl Instruction {
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn immediate(&self) -> [u8; 2] {
+        let start = if self.bytes[0] == 0xcb { 2 } else { 1 };
+        let byte = |i: usize| self.bytes.get(start + i).cloned().unwrap_or(0);
+        [byte(0), byte(1)]
+    }
+
+    fn next_addr(&self) -> usize {
+        self.addr as usize + self.size()
+    }
+
+    pub fn text(&self) -> String {
+        match self.opcode {
+            Some(opcode) => opcode.shorthand(&self.immediate(), self.next_addr()),
+            None => format!("DB 0x{:02x}", self.bytes[0]),
+        }
+    }
+
+    //where a JP, JR, CALL or RST goes, when it's known without running it
+    pub fn target(&self) -> Option<u16> {
+        let opcode = self.opcode?;
+        let immediate = self.immediate();
+        for op in opcode.operands {
+            match *op {
+                "a16" => return Some(immediate16(&immediate)),
+                "r8" if opcode.mnemonic == "JR" => {
+                    return Some((immediate[0] as i8 as i32 + self.next_addr() as i32) as u16)
+                }
+                op if opcode.mnemonic == "RST" => return u16::from_str_radix(&op[..2], 16).ok(),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    //the bank the target is found in
+    pub fn target_bank(&self, target: u16) -> usize {
+        if target < BANK_SIZE as u16 {
+            0
+        } else {
+            self.bank
+        }
+    }
+
+    //replaces the target address in text with its label, if it has one
+    pub fn label_target(&self, text: String, symbols: &Symbols) -> String {
+        let target = match self.target() {
+            Some(target) => target,
+            None => return text,
+        };
+        //RST only takes the vector, not a label
+        if self.opcode.map(|opcode| opcode.mnemonic) == Some("RST") {
+            return text;
+        }
+
+        match symbols.label(self.target_bank(target), target) {
+            Some(label) => {
+                let addr = format!("${:04x}", target);
+                match text.rfind(&addr) {
+                    Some(pos) => format!("{}{}{}", &text[..pos], label, &text[pos + addr.len()..]),
+                    None => text,
+                }
+            }
+            None => text,
+        }
+    }
+
+    pub fn rgbds(&self) -> String {
+        match self.opcode {
+            //STOP with anything but 0 after it wouldn't assemble back to the same bytes
+            Some(opcode) if opcode.mnemonic != "STOP" || self.bytes[1] == 0 => {
+                opcode.rgbds(&self.immediate(), self.next_addr())
+            }
+            _ => {
+                let bytes: Vec<String> = self.bytes.iter().map(|b| format!("${:02x}", b)).collect();
+                format!("db {}", bytes.join(", "))
+            }
+        }
+    }
+}
+
+//decodes the instruction at the start of data, which is found at addr in the given bank.
+//bytes that aren't an instruction, or one cut short by the end of data, come out as single bytes
+pub fn decode(data: &[u8], bank: usize, addr: u16) -> Instruction {
+    let mut instr = data[0] as u16;
+    if instr == 0xcb && data.len() > 1 {
+        instr = (instr << 8) | data[1] as u16;
+    }
+
+    let opcode = opcode(instr).filter(|opcode| opcode.bytes <= data.len());
+    let size = opcode.map(|opcode| opcode.bytes).unwrap_or(1);
+
+    Instruction {
+        bank: bank,
+        addr: addr,
+        bytes: data[..size].to_vec(),
+        opcode: opcode,
+    }
+}
+
+//decodes all of data, as if it was mapped at start
+pub fn disassemble(data: &[u8], bank: usize, start: u16) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    let mut offset = 0;
+    while offset < data.len() {
+        let instruction = decode(&data[offset..], bank, start.wrapping_add(offset as u16));
+        offset += instruction.size();
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+//bank 0 is always at 0x0000, the others are switched in at 0x4000
+pub fn bank_start(bank: usize) -> u16 {
+    if bank == 0 {
+        0
+    } else {
+        BANK_SIZE as u16
+    }
+}
+
+//decodes a ROM bank from the address from, until the end of the bank
+pub fn disassemble_bank(rom: &[u8], bank: usize, from: u16) -> io::Result<Vec<Instruction>> {
+    let start = bank_start(bank) as usize;
+    if (from as usize) < start || from as usize >= start + BANK_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Address {:04x} is not in bank {}", from, bank),
+        ));
+    }
+
+    let bank_offset = bank * BANK_SIZE;
+    if bank_offset + BANK_SIZE > rom.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("The ROM has no bank {}", bank),
+        ));
+    }
+
+    let data = &rom[bank_offset + from as usize - start..bank_offset + BANK_SIZE];
+    Ok(disassemble(data, bank, from))
+}
+
+//writes the instructions as a RGBDS section that assembles back to the same bytes.
+//with symbols, labels are defined where they point and used as jump targets
+pub fn write_rgbds<W: Write>(
+    out: &mut W,
+    instructions: &[Instruction],
+    symbols: Option<&Symbols>,
+) -> io::Result<()> {
+    let first = match instructions.first() {
+        Some(first) => first,
+        None => return Ok(()),
+    };
+
+    if first.bank == 0 {
+        writeln!(out, "SECTION \"ROM Bank $00\", ROM0[${:04x}]", first.addr)?;
+    } else {
+        writeln!(
+            out,
+            "SECTION \"ROM Bank ${:02x}\", ROMX[${:04x}], BANK[${:02x}]",
+            first.bank, first.addr, first.bank
+        )?;
+    }
+    writeln!(out)?;
+
+    for instruction in instructions {
+        let mut text = instruction.rgbds();
+        if let Some(symbols) = symbols {
+            if let Some(label) = symbols.label(instruction.bank, instruction.addr) {
+                writeln!(out, "{}:", label)?;
+            }
+            text = instruction.label_target(text, symbols);
+        }
+        writeln!(out, "\t{:<24}; ${:04x}", text, instruction.addr)?;
+    }
+    Ok(())
+}
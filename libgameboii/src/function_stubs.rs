@@ -178,13 +178,13 @@ unsafe fn stubs(cpu: &mut CPU, dummy: &str) {
 
 		"DI" => {
 			//----------------
-			cpu.enable_interrupts(false);
+			cpu.disable_interrupts();
 			//----------------
 		}
 
 		"EI" => {
 			//----------------
-			cpu.enable_interrupts(true);
+			cpu.enable_interrupts();
 			//----------------
 		}
 
@@ -355,23 +355,23 @@ unsafe fn stubs(cpu: &mut CPU, dummy: &str) {
 
 		"RET" => {
 			//----------------
-			cpu.PC = cpu.pop16();
+			cpu.ret();
 			//----------------
 		}
 
 		"RETI" => {
 			//----------------
-			cpu.PC = cpu.pop16();
-			//TODO do we want the delay here?
-			cpu.enable_interrupts(true);
+			cpu.return_from_interrupt();
 			//----------------
 		}
 
 		"RET_bool" => {
 			let reg0 = cpu.c();
 			//----------------
+			//the condition takes a cycle before the pops
+			cpu.internal_cycle();
 			if reg0 {
-				cpu.PC = cpu.pop16();
+				cpu.ret();
 			}
 			//----------------
 		}
@@ -462,9 +462,7 @@ unsafe fn stubs(cpu: &mut CPU, dummy: &str) {
 		"RST_u16" => {
 			let reg0 = 0x38;
 			//----------------
-			let pc = cpu.PC;
-			cpu.push16(pc);
-			cpu.PC = reg0;
+			cpu.call(reg0);
 			//----------------
 		}
 
@@ -0,0 +1,476 @@
+extern crate std;
+
+use cpu::CPU;
+use debugger::{set_register, DebugHook};
+use std::cell::RefCell;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::ops::Range;
+use std::rc::Rc;
+use watchpoint::{WatchCondition, WatchHit};
+
+//the order of the registers in the g packet, each one 16 bits little endian
+const REGISTERS: [&str; 6] = ["af", "bc", "de", "hl", "sp", "pc"];
+
+//checking the socket for a break every instruction would be way too slow
+const INTERRUPT_POLL_INSTRUCTIONS: u32 = 4096;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+const PACKET_SIZE: usize = 0x1000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+impl WatchKind {
+    fn from_type(kind: u8) -> Option<Self> {
+        match kind {
+            2 => Some(WatchKind::Write),
+            3 => Some(WatchKind::Read),
+            4 => Some(WatchKind::Access),
+            _ => None,
+        }
+    }
+
+    fn conditions(self) -> &'static [WatchCondition] {
+        match self {
+            WatchKind::Write => &[WatchCondition::Write],
+            WatchKind::Read => &[WatchCondition::Read],
+            WatchKind::Access => &[WatchCondition::Read, WatchCondition::Write],
+        }
+    }
+
+    fn stop_reason(self) -> &'static str {
+        match self {
+            WatchKind::Write => "watch",
+            WatchKind::Read => "rwatch",
+            WatchKind::Access => "awatch",
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, b| sum.wrapping_add(*b))
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn read_register(cpu: &CPU, index: usize) -> u16 {
+    unsafe {
+        match index {
+            0 => cpu.AF.r16,
+            1 => cpu.BC.r16,
+            2 => cpu.DE.r16,
+            3 => cpu.HL.r16,
+            4 => cpu.SP,
+            _ => cpu.PC,
+        }
+    }
+}
+
+fn encode_register(value: u16) -> String {
+    encode_hex(&[value as u8, (value >> 8) as u8])
+}
+
+fn decode_register(text: &str) -> Option<u16> {
+    let bytes = decode_hex(text)?;
+    if bytes.len() != 2 {
+        return None;
+    }
+    Some(bytes[0] as u16 | (bytes[1] as u16) << 8)
+}
+
+//"addr,len" as found in m, M and Z packets
+fn parse_range(text: &str) -> Option<Range<usize>> {
+    let mut parts = text.split(',');
+    let start = parse_hex(parts.next()?)?;
+    let len = parse_hex(parts.next()?)?;
+    if start + len > 0x10000 {
+        return None;
+    }
+    Some(start..start + len)
+}
+
+//lets gdb (or anything else speaking its remote serial protocol) debug the emulator over TCP
+pub struct GdbStub {
+    stream: TcpStream,
+    breakpoints: Vec<u16>,
+    watches: Vec<(Range<usize>, WatchKind)>,
+    watch_hit: Rc<RefCell<Option<WatchHit>>>,
+
+    //stop before the next instruction, with this signal
+    stop: Option<u8>,
+    last_signal: u8,
+    //gdb is waiting for a stop reply
+    running: bool,
+    detached: bool,
+    until_poll: u32,
+}
+
+impl Drop for GdbStub {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl GdbStub {
+    fn from_stream(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+
+        Ok(GdbStub {
+            stream: stream,
+            breakpoints: vec![],
+            watches: vec![],
+            watch_hit: Rc::new(RefCell::new(None)),
+            //gdb expects the target to be halted when it connects
+            stop: Some(SIGTRAP),
+            last_signal: SIGTRAP,
+            running: false,
+            detached: false,
+            until_poll: INTERRUPT_POLL_INSTRUCTIONS,
+        })
+    }
+
+    //only on localhost, there's no authentication of any kind
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        Self::accept(&listener)
+    }
+
+    pub fn accept(listener: &TcpListener) -> io::Result<Self> {
+        let (stream, _) = listener.accept()?;
+        Self::from_stream(stream)
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0; 1];
+        self.stream.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+
+            //wait for the ack, resend on a nack
+            loop {
+                match self.read_byte()? {
+                    b'+' => return Ok(()),
+                    b'-' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn receive(&mut self) -> io::Result<String> {
+        loop {
+            //skip acks and breaks until the start of a packet
+            while self.read_byte()? != b'$' {}
+
+            let mut data = vec![];
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let sum = [self.read_byte()?, self.read_byte()?];
+            let expected = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|sum| u8::from_str_radix(sum, 16).ok());
+
+            if expected == Some(checksum(&data)) {
+                self.stream.write_all(b"+")?;
+                return Ok(String::from_utf8_lossy(&data).into_owned());
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    //gdb sends a lone 0x03 when the user hits ctrl-c
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        let mut byte = [0; 1];
+        self.stream.set_nonblocking(true)?;
+        let result = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+
+        match result {
+            Ok(0) => Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "gdb disconnected",
+            )),
+            Ok(_) => Ok(byte[0] == 0x03),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn stop_reply(&mut self, signal: u8) -> String {
+        if let Some(hit) = self.watch_hit.borrow_mut().take() {
+            let addr = hit.addr as usize;
+            let watch = self
+                .watches
+                .iter()
+                .find(|watch| watch.0.start <= addr && addr < watch.0.end);
+            if let Some(&(_, kind)) = watch {
+                return format!("T{:02x}{}:{:x};", signal, kind.stop_reason(), addr);
+            }
+        }
+        format!("S{:02x}", signal)
+    }
+
+    fn serve(&mut self, cpu: &mut CPU, signal: u8) -> io::Result<()> {
+        self.last_signal = signal;
+        if self.running {
+            self.running = false;
+            let reply = self.stop_reply(signal);
+            self.send(&reply)?;
+        }
+
+        loop {
+            let packet = self.receive()?;
+            if let Some(reply) = self.handle(cpu, &packet) {
+                self.send(&reply)?;
+            }
+            if self.running || self.detached || cpu.should_exit {
+                return Ok(());
+            }
+        }
+    }
+
+    fn detach(&mut self, cpu: &mut CPU) {
+        self.detached = true;
+        self.stop = None;
+        self.breakpoints.clear();
+        self.remove_watches(cpu);
+    }
+
+    fn resume(&mut self, cpu: &mut CPU, args: &str, step: bool) -> Option<String> {
+        if !args.is_empty() {
+            match parse_hex(args) {
+                Some(addr) => cpu.PC = addr as u16,
+                None => return Some("E01".to_owned()),
+            }
+        }
+        if step {
+            self.stop = Some(SIGTRAP);
+        }
+        self.running = true;
+        None
+    }
+
+    fn write_registers(&mut self, cpu: &mut CPU, args: &str) -> Option<String> {
+        let values: Option<Vec<u16>> = (0..REGISTERS.len())
+            .map(|i| decode_register(args.get(i * 4..i * 4 + 4)?))
+            .collect();
+
+        let values = values?;
+        for (name, value) in REGISTERS.iter().zip(values) {
+            set_register(cpu, name, value);
+        }
+        Some("OK".to_owned())
+    }
+
+    fn write_register(&mut self, cpu: &mut CPU, args: &str) -> Option<String> {
+        let mut parts = args.split('=');
+        let index = parse_hex(parts.next()?)?;
+        let value = decode_register(parts.next()?)?;
+
+        set_register(cpu, REGISTERS.get(index)?, value);
+        Some("OK".to_owned())
+    }
+
+    fn read_memory(&mut self, cpu: &CPU, args: &str) -> Option<String> {
+        let range = parse_range(args)?;
+        if range.len() * 2 > PACKET_SIZE {
+            return None;
+        }
+        //not through CPU::address, gdb looking at memory shouldn't trip watchpoints
+        Some(encode_hex(&cpu.RAM[range]))
+    }
+
+    fn write_memory(&mut self, cpu: &mut CPU, args: &str) -> Option<String> {
+        let mut parts = args.split(':');
+        let range = parse_range(parts.next()?)?;
+        let data = decode_hex(parts.next()?)?;
+        if data.len() != range.len() {
+            return None;
+        }
+
+        //through the bus, so mirrors and registers behave like a game write
+        for (addr, val) in range.zip(data) {
+            cpu.set_address(addr as u16, val);
+        }
+        //but it's not the game touching the watched memory
+        cpu.watchpoints.borrow_mut().take_pause();
+        self.watch_hit.borrow_mut().take();
+        Some("OK".to_owned())
+    }
+
+    fn remove_watches(&mut self, cpu: &mut CPU) {
+        let mut watchpoints = cpu.watchpoints.borrow_mut();
+        for (range, kind) in self.watches.drain(..) {
+            for condition in kind.conditions() {
+                watchpoints.remove(range.clone(), *condition);
+            }
+        }
+    }
+
+    //Z and z packets: "type,addr,kind"
+    fn set_breakpoint(&mut self, cpu: &mut CPU, args: &str, insert: bool) -> Option<String> {
+        let mut parts = args.splitn(2, ',');
+        let kind = parts.next()?.parse::<u8>().ok()?;
+        let range = parse_range(parts.next()?)?;
+
+        if kind == 0 || kind == 1 {
+            let addr = range.start as u16;
+            self.breakpoints.retain(|bp| *bp != addr);
+            if insert {
+                self.breakpoints.push(addr);
+            }
+            return Some("OK".to_owned());
+        }
+
+        let kind = match WatchKind::from_type(kind) {
+            Some(kind) => kind,
+            //unsupported, gdb will try something else
+            None => return Some("".to_owned()),
+        };
+
+        if insert && self.watches.is_empty() {
+            let watch_hit = self.watch_hit.clone();
+            cpu.watchpoints
+                .borrow_mut()
+                .set_callback(Box::new(move |hit| {
+                    *watch_hit.borrow_mut() = Some(*hit);
+                    true
+                }));
+        }
+
+        let mut watchpoints = cpu.watchpoints.borrow_mut();
+        for condition in kind.conditions() {
+            watchpoints.remove(range.clone(), *condition);
+            if insert {
+                watchpoints.add(range.clone(), *condition);
+            }
+        }
+        self.watches.retain(|watch| *watch != (range.clone(), kind));
+        if insert {
+            self.watches.push((range, kind));
+        }
+        Some("OK".to_owned())
+    }
+
+    //returns the reply, if any. Malformed packets get an error reply
+    fn handle(&mut self, cpu: &mut CPU, packet: &str) -> Option<String> {
+        if packet.is_empty() {
+            return Some("".to_owned());
+        }
+        let (command, args) = packet.split_at(1);
+
+        let reply = match command {
+            "?" => Some(format!("S{:02x}", self.last_signal)),
+            "g" => Some(
+                (0..REGISTERS.len())
+                    .map(|i| encode_register(read_register(cpu, i)))
+                    .collect(),
+            ),
+            "G" => self.write_registers(cpu, args),
+            "p" => parse_hex(args)
+                .filter(|index| *index < REGISTERS.len())
+                .map(|index| encode_register(read_register(cpu, index))),
+            "P" => self.write_register(cpu, args),
+            "m" => self.read_memory(cpu, args),
+            "M" => self.write_memory(cpu, args),
+            "c" => return self.resume(cpu, args, false),
+            "s" => return self.resume(cpu, args, true),
+            "Z" => self.set_breakpoint(cpu, args, true),
+            "z" => self.set_breakpoint(cpu, args, false),
+            //there's only one thread
+            "H" => Some("OK".to_owned()),
+            "D" => {
+                self.detach(cpu);
+                Some("OK".to_owned())
+            }
+            "k" => {
+                cpu.should_exit = true;
+                return None;
+            }
+            "q" if args.starts_with("Supported") => Some(format!("PacketSize={:x}", PACKET_SIZE)),
+            "q" if args == "Attached" => Some("1".to_owned()),
+            //anything else is unsupported
+            _ => Some("".to_owned()),
+        };
+
+        Some(reply.unwrap_or_else(|| "E01".to_owned()))
+    }
+
+    fn check_stop(&mut self, cpu: &CPU) -> io::Result<()> {
+        if self.stop.is_some() {
+            return Ok(());
+        }
+
+        if self.breakpoints.contains(&cpu.PC) {
+            self.stop = Some(SIGTRAP);
+            return Ok(());
+        }
+
+        self.until_poll -= 1;
+        if self.until_poll == 0 {
+            self.until_poll = INTERRUPT_POLL_INSTRUCTIONS;
+            if self.poll_interrupt()? {
+                self.stop = Some(SIGINT);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DebugHook for GdbStub {
+    fn before_instruction(&mut self, cpu: &mut CPU) {
+        if self.detached {
+            return;
+        }
+
+        let result = self.check_stop(cpu).and_then(|_| match self.stop.take() {
+            Some(signal) => self.serve(cpu, signal),
+            None => Ok(()),
+        });
+
+        //losing gdb shouldn't take the game down with it
+        if result.is_err() {
+            self.detach(cpu);
+        }
+    }
+
+    fn pause(&mut self) {
+        if !self.detached && self.stop.is_none() {
+            self.stop = Some(SIGTRAP);
+        }
+    }
+}
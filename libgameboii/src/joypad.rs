@@ -0,0 +1,25 @@
+use bit_field::BitField;
+
+//the buttons, as a bitmask of the pressed ones.
+//the low nibble are the directions, the high nibble the other buttons
+pub const RIGHT: u8 = 1 << 0;
+pub const LEFT: u8 = 1 << 1;
+pub const UP: u8 = 1 << 2;
+pub const DOWN: u8 = 1 << 3;
+pub const A: u8 = 1 << 4;
+pub const B: u8 = 1 << 5;
+pub const SELECT: u8 = 1 << 6;
+pub const START: u8 = 1 << 7;
+
+//what the game reads from P1. It selects a group of buttons by pulling P14 or P15 low,
+//then the pressed buttons of that group read as low bits
+pub fn p1_register(select: u8, pressed: u8) -> u8 {
+    let mut val = 0xc0 | (select & 0x30) | 0x0f;
+    if !select.get_bit(4) {
+        val &= !(pressed & 0x0f);
+    }
+    if !select.get_bit(5) {
+        val &= !(pressed >> 4);
+    }
+    val
+}
@@ -1,3 +1,4 @@
+extern crate bincode;
 extern crate bit_field;
 extern crate image;
 
@@ -7,11 +8,29 @@ extern crate serde;
 extern crate serde_json;
 
 mod address;
+pub mod cheats;
+pub mod coverage;
 pub mod cpu;
 pub mod debug_log;
+pub mod debugger;
+pub mod disasm;
 mod function_stubs;
+pub mod gdb_stub;
 pub mod interpreter;
+pub mod joypad;
+pub mod link_cable;
+pub mod movie;
+pub mod net_link;
+mod opcode_table;
 pub mod ppu;
+pub mod printer;
+pub mod profiler;
+pub mod ram_search;
+pub mod rewind;
+pub mod save_state;
+pub mod serial;
+pub mod symbols;
+pub mod watchpoint;
 
 use std::fs::File;
 use std::io::Read;
@@ -25,3 +44,11 @@ pub fn open_rom<P: AsRef<Path>>(rom_path: &P) -> std::io::Result<Vec<u8>> {
 
     Ok(content)
 }
+
+//identifies the exact ROM that save states and movies were made with.
+//the header checksum can't tell apart hacks and bad dumps, so hash it all (FNV-1a)
+pub fn rom_checksum(rom: &[u8]) -> u32 {
+    rom.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
@@ -0,0 +1,54 @@
+use serial::SerialDevice;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+struct Wire {
+    //each side's out line, when it's waiting for an external clock
+    lines: [Option<bool>; 2],
+    //bits clocked in by the other side, shifted when this side gets ticked
+    pulses: [Option<bool>; 2],
+}
+
+//one end of a link cable between two gameboys running in the same process
+pub struct LinkPort {
+    wire: Rc<RefCell<Wire>>,
+    side: usize,
+}
+
+pub fn connect() -> (LinkPort, LinkPort) {
+    let wire = Rc::new(RefCell::new(Wire {
+        lines: [None, None],
+        pulses: [None, None],
+    }));
+
+    (
+        LinkPort {
+            wire: wire.clone(),
+            side: 0,
+        },
+        LinkPort {
+            wire: wire,
+            side: 1,
+        },
+    )
+}
+
+impl SerialDevice for LinkPort {
+    fn exchange_bit(&mut self, out: bool) -> Option<bool> {
+        let mut wire = self.wire.borrow_mut();
+        let other = 1 - self.side;
+
+        wire.pulses[other] = Some(out);
+        //if the other side isn't listening, the line stays high
+        Some(wire.lines[other].unwrap_or(true))
+    }
+
+    fn external_clock(&mut self, out: Option<bool>) -> Option<bool> {
+        let mut wire = self.wire.borrow_mut();
+        wire.lines[self.side] = out;
+
+        //a pulse that comes while not listening is lost
+        let pulse = wire.pulses[self.side].take();
+        out.and(pulse)
+    }
+}
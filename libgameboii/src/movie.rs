@@ -0,0 +1,172 @@
+extern crate std;
+
+use cpu::CPU;
+use ppu::FRAME_CLOCKS;
+use save_state::SaveState;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: [u8; 4] = *b"GBMV";
+//bump it whenever the layout of the movies changes
+const VERSION: u16 = 1;
+
+fn invalid_data<E: ToString>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+fn write_u32<W: Write>(out: &mut W, val: u32) -> io::Result<()> {
+    out.write_all(&[(val >> 24) as u8, (val >> 16) as u8, (val >> 8) as u8, val as u8])
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    input.read_exact(&mut buf)?;
+    Ok(buf.iter().fold(0, |val, b| (val << 8) | *b as u32))
+}
+
+//the buttons held during each frame of a run. Given the same ROM and starting point
+//the emulator is deterministic, so replaying them replays the whole run.
+//On file it's a header with the format version and the ROM checksum, the optional
+//starting save state, and then a byte of buttons per frame
+pub struct Movie {
+    rom_checksum: u32,
+    start_state: Option<SaveState>,
+    start_clock: u64,
+    frames: Vec<u8>,
+}
+
+impl Movie {
+    //without a start state, the movie starts from power on
+    pub fn new(cpu: &CPU, start_state: Option<SaveState>) -> Self {
+        Movie {
+            rom_checksum: cpu.rom_checksum(),
+            start_clock: start_state.as_ref().map_or(0, |state| state.current_clock()),
+            start_state: start_state,
+            frames: vec![],
+        }
+    }
+
+    pub fn start_state(&self) -> Option<&SaveState> {
+        self.start_state.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn check_rom(&self, cpu: &CPU) -> io::Result<()> {
+        if self.rom_checksum != cpu.rom_checksum() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The movie was recorded with a different ROM",
+            ));
+        }
+        Ok(())
+    }
+
+    //the frame that starts at this clock, if any
+    fn frame_at(&self, current_clock: u64) -> Option<usize> {
+        let clocks = current_clock.checked_sub(self.start_clock)?;
+        if clocks % FRAME_CLOCKS == 0 {
+            Some((clocks / FRAME_CLOCKS) as usize)
+        } else {
+            None
+        }
+    }
+
+    //call these once per clock, before ticking the CPU.
+    //the buttons only change at the start of a frame, so they're replayed on the same clock
+    pub fn record(&mut self, cpu: &mut CPU, current_clock: u64, buttons: u8) {
+        if let Some(frame) = self.frame_at(current_clock) {
+            if frame == self.frames.len() {
+                self.frames.push(buttons);
+                cpu.set_buttons(buttons);
+            }
+        }
+    }
+
+    //returns false once the movie is over
+    pub fn play(&self, cpu: &mut CPU, current_clock: u64) -> bool {
+        match self.frame_at(current_clock) {
+            Some(frame) => match self.frames.get(frame) {
+                Some(&buttons) => {
+                    cpu.set_buttons(buttons);
+                    true
+                }
+                None => false,
+            },
+            None => true,
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(&MAGIC)?;
+        out.write_all(&[(VERSION >> 8) as u8, VERSION as u8])?;
+        write_u32(out, self.rom_checksum)?;
+
+        match self.start_state {
+            Some(ref state) => {
+                let mut buf = vec![];
+                state.write_to(&mut buf)?;
+                write_u32(out, buf.len() as u32)?;
+                out.write_all(&buf)?;
+            }
+            None => write_u32(out, 0)?,
+        }
+
+        write_u32(out, self.frames.len() as u32)?;
+        out.write_all(&self.frames)
+    }
+
+    pub fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let mut header = [0; 6];
+        input.read_exact(&mut header)?;
+
+        if header[0..4] != MAGIC {
+            return Err(invalid_data("Not a movie"));
+        }
+
+        let version = ((header[4] as u16) << 8) | header[5] as u16;
+        if version != VERSION {
+            return Err(invalid_data(format!("Unsupported movie version {}", version)));
+        }
+
+        let rom_checksum = read_u32(input)?;
+
+        let state_len = read_u32(input)?;
+        let start_state = if state_len > 0 {
+            let mut buf = vec![0; state_len as usize];
+            input.read_exact(&mut buf)?;
+            Some(SaveState::read_from(&mut &buf[..])?)
+        } else {
+            None
+        };
+
+        let frame_count = read_u32(input)?;
+        let mut frames = vec![0; frame_count as usize];
+        input.read_exact(&mut frames)?;
+
+        Ok(Movie {
+            rom_checksum: rom_checksum,
+            start_clock: start_state.as_ref().map_or(0, |state| state.current_clock()),
+            start_state: start_state,
+            frames: frames,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: &P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_to(&mut out)?;
+        out.flush()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: &P) -> io::Result<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+}
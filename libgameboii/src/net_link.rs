@@ -0,0 +1,270 @@
+extern crate std;
+
+use ppu::FRAME_CLOCKS;
+use serial::SerialDevice;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+
+//the two emulators wait for each other once per frame, so neither can run ahead
+const SYNC_INTERVAL_CLOCKS: u64 = FRAME_CLOCKS;
+
+const FRAME_PULSE: u8 = 0;
+const FRAME_REPLY: u8 = 1;
+const FRAME_SYNC: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Frame {
+    //the other side clocked a bit out on the internal clock, at this clock
+    Pulse(bool, u64),
+    //the bit shifted out in answer to our own pulse
+    Reply(bool),
+    //the other side reached this clock
+    Sync(u64),
+}
+
+fn write_clock(buf: &mut Vec<u8>, clock: u64) {
+    for i in 0..8 {
+        buf.push((clock >> (56 - i * 8)) as u8);
+    }
+}
+
+fn read_clock<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    input.read_exact(&mut buf)?;
+    Ok(buf.iter().fold(0, |clock, b| (clock << 8) | *b as u64))
+}
+
+fn read_bit<R: Read>(input: &mut R) -> io::Result<bool> {
+    let mut bit = [0; 1];
+    input.read_exact(&mut bit)?;
+    Ok(bit[0] != 0)
+}
+
+fn write_frame<W: Write>(out: &mut W, frame: Frame) -> io::Result<()> {
+    let mut buf = vec![];
+    match frame {
+        Frame::Pulse(bit, clock) => {
+            buf.extend_from_slice(&[FRAME_PULSE, bit as u8]);
+            write_clock(&mut buf, clock);
+        }
+        Frame::Reply(bit) => buf.extend_from_slice(&[FRAME_REPLY, bit as u8]),
+        Frame::Sync(clock) => {
+            buf.push(FRAME_SYNC);
+            write_clock(&mut buf, clock);
+        }
+    }
+    out.write_all(&buf)
+}
+
+fn read_frame<R: Read>(input: &mut R) -> io::Result<Frame> {
+    let mut tag = [0; 1];
+    input.read_exact(&mut tag)?;
+
+    match tag[0] {
+        FRAME_PULSE => {
+            let bit = read_bit(input)?;
+            Ok(Frame::Pulse(bit, read_clock(input)?))
+        }
+        FRAME_REPLY => Ok(Frame::Reply(read_bit(input)?)),
+        FRAME_SYNC => Ok(Frame::Sync(read_clock(input)?)),
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unknown link frame {:02x}", other),
+        )),
+    }
+}
+
+fn disconnected() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "Link cable disconnected")
+}
+
+//a link cable to a gameboy running in another process
+pub struct NetLink {
+    stream: TcpStream,
+    incoming: Receiver<Frame>,
+    error: Option<io::Error>,
+
+    clock: u64,
+    line: Option<bool>,
+    pulse: Option<(bool, u64)>,
+    reply: Option<bool>,
+    sync_clock: Option<u64>,
+    peer_clock: Option<u64>,
+}
+
+impl Drop for NetLink {
+    fn drop(&mut self) {
+        //also wakes up the reader thread, which holds a clone of the stream
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl NetLink {
+    fn from_stream(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+
+        //a thread does the blocking reads, so polling for frames every clock is cheap
+        let mut reader = stream.try_clone()?;
+        let (sender, incoming) = channel();
+        thread::spawn(move || {
+            while let Ok(frame) = read_frame(&mut reader) {
+                if sender.send(frame).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(NetLink {
+            stream: stream,
+            incoming: incoming,
+            error: None,
+            clock: 0,
+            line: None,
+            pulse: None,
+            reply: None,
+            sync_clock: None,
+            peer_clock: None,
+        })
+    }
+
+    //only on localhost, anyone who can connect gets the link cable
+    pub fn listen(port: u16) -> io::Result<Self> {
+        Self::listen_on(("127.0.0.1", port))
+    }
+
+    //to let other machines connect, eg. on ("0.0.0.0", port)
+    pub fn listen_on<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        Self::accept(&listener)
+    }
+
+    pub fn accept(listener: &TcpListener) -> io::Result<Self> {
+        let (stream, _) = listener.accept()?;
+        Self::from_stream(stream)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Self::from_stream(TcpStream::connect(addr)?)
+    }
+
+    fn handle_frame(&mut self, frame: Frame) {
+        match frame {
+            Frame::Pulse(bit, clock) => self.pulse = Some((bit, clock)),
+            Frame::Reply(bit) => self.reply = Some(bit),
+            Frame::Sync(clock) => self.peer_clock = Some(clock),
+        }
+    }
+
+    fn wait_frame(&mut self) -> io::Result<()> {
+        let frame = self.incoming.recv().map_err(|_| disconnected())?;
+        self.handle_frame(frame);
+        Ok(())
+    }
+
+    fn answer_pulse(&mut self) -> io::Result<Option<bool>> {
+        let (bit, _) = self.pulse.take().unwrap();
+
+        //when not listening the line stays high, and nothing is shifted in
+        write_frame(&mut self.stream, Frame::Reply(self.line.unwrap_or(true)))?;
+        Ok(self.line.map(|_| bit))
+    }
+
+    fn waiting_for_sync(&self) -> bool {
+        match (self.sync_clock, self.peer_clock) {
+            (Some(sync), Some(peer)) => peer < sync,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    fn update(&mut self, current_clock: u64) -> io::Result<Option<bool>> {
+        loop {
+            match self.incoming.try_recv() {
+                Ok(frame) => self.handle_frame(frame),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Err(disconnected()),
+            }
+        }
+
+        if current_clock % SYNC_INTERVAL_CLOCKS == 0 {
+            write_frame(&mut self.stream, Frame::Sync(current_clock))?;
+            self.sync_clock = Some(current_clock);
+        }
+
+        loop {
+            //the other side is blocked until we answer, but let our clock catch up first.
+            //if we're the ones waiting, it can't wait for us
+            let pulse_due = match self.pulse {
+                Some((_, clock)) => clock <= current_clock || self.waiting_for_sync(),
+                None => false,
+            };
+            if pulse_due {
+                return self.answer_pulse();
+            }
+
+            if !self.waiting_for_sync() {
+                return Ok(None);
+            }
+            self.wait_frame()?;
+        }
+    }
+
+    fn send_pulse(&mut self, out: bool) -> io::Result<bool> {
+        //external_clock already counted this clock
+        let current_clock = self.clock - 1;
+        write_frame(&mut self.stream, Frame::Pulse(out, current_clock))?;
+
+        loop {
+            if let Some(bit) = self.reply.take() {
+                return Ok(bit);
+            }
+            //both sides are on the internal clock
+            if self.pulse.is_some() {
+                self.answer_pulse()?;
+            }
+            self.wait_frame()?;
+        }
+    }
+}
+
+impl SerialDevice for NetLink {
+    fn exchange_bit(&mut self, out: bool) -> Option<bool> {
+        if self.error.is_some() {
+            return Some(true);
+        }
+
+        match self.send_pulse(out) {
+            Ok(bit) => Some(bit),
+            Err(error) => {
+                self.error = Some(error);
+                Some(true)
+            }
+        }
+    }
+
+    fn external_clock(&mut self, out: Option<bool>) -> Option<bool> {
+        let current_clock = self.clock;
+        self.clock += 1;
+        self.line = out;
+
+        if self.error.is_some() {
+            return None;
+        }
+
+        match self.update(current_clock) {
+            Ok(bit) => bit,
+            Err(error) => {
+                self.error = Some(error);
+                None
+            }
+        }
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
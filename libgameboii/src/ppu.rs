@@ -2,10 +2,10 @@ extern crate std;
 
 use address;
 use bit_field::BitField;
-use cpu::CPU;
 use image::Pixel;
 use image::Rgba;
 use image::RgbaImage;
+use std::io;
 
 const MAX_SCANLINES: u8 = 153;
 const LY_VALUES_COUNT: u8 = MAX_SCANLINES + 1;
@@ -16,6 +16,8 @@ const H_BLANK_PHASE_DURATION_CLOCKS: u64 = 51 * 4;
 const V_BLANK_PHASE_DURATION_CLOCKS: u64 = OAM_SEARCH_PHASE_DURATION_CLOCKS
     + PIXEL_TRANSFER_PHASE_DURATION_CLOCKS
     + H_BLANK_PHASE_DURATION_CLOCKS;
+//a whole frame, scanlines and vblank included
+pub const FRAME_CLOCKS: u64 = V_BLANK_PHASE_DURATION_CLOCKS * LY_VALUES_COUNT as u64;
 
 pub const RESOLUTION_W: u8 = 160;
 pub const RESOLUTION_H: u8 = 144;
@@ -30,18 +32,14 @@ const TILE_RESOLUTION_W: u8 = 32;
 const TILE_RESOLUTION_H: u8 = 32;
 const TILE_SIZE_BYTES: usize = 8 * 2;
 
-#[allow(unused)]
 const MAX_SPRITES: u32 = 40;
-#[allow(unused)]
 const MAX_SPRITES_PER_LINE: u32 = 10;
-#[allow(unused)]
 const MAX_SPRITE_SIZE_W: u32 = 8;
-#[allow(unused)]
 const MAX_SPRITE_SIZE_H: u32 = 16;
 #[allow(unused)]
 const MIN_SPRITE_SIZE_W: u32 = 8;
-#[allow(unused)]
 const MIN_SPRITE_SIZE_H: u32 = 8;
+const SPRITE_SIZE_BYTES: usize = 4;
 
 #[derive(Eq, PartialEq)]
 enum TileDataAddressing {
@@ -69,14 +67,25 @@ impl LCDCValues {
     fn double_obj(&self) -> bool {
         self.raw.get_bit(2)
     }
-    fn tile_map_addr(&self) -> usize {
-        if self.raw.get_bit(3) {
+    fn obj_height(&self) -> u32 {
+        if self.double_obj() {
+            MAX_SPRITE_SIZE_H
+        } else {
+            MIN_SPRITE_SIZE_H
+        }
+    }
+    fn pick_tile_map(second: bool) -> usize {
+        if second {
             address::TILE_MAP1.start
         } else {
             address::TILE_MAP0.start
         }
     }
 
+    fn tile_map_addr(&self) -> usize {
+        Self::pick_tile_map(self.raw.get_bit(3))
+    }
+
     fn pick_tile_bank(unsigned: bool) -> (usize, TileDataAddressing) {
         if unsigned {
             (
@@ -91,20 +100,29 @@ impl LCDCValues {
         }
     }
 
+    //the background and the window share the tile data
     fn tile_data_addr_and_addressing(&self) -> (usize, TileDataAddressing) {
         Self::pick_tile_bank(self.raw.get_bit(4))
     }
     fn windowing_on(&self) -> bool {
         self.raw.get_bit(5)
     }
-    fn window_tile_data_addr_and_addressing(&self) -> (usize, TileDataAddressing) {
-        Self::pick_tile_bank(self.raw.get_bit(6))
+    fn window_tile_map_addr(&self) -> usize {
+        Self::pick_tile_map(self.raw.get_bit(6))
     }
     fn lcd_on(&self) -> bool {
         self.raw.get_bit(7)
     }
 }
 
+//the custom shades this emulator draws with, or evenly spaced grays like the
+//reference images of test ROMs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shades {
+    Default,
+    Grayscale,
+}
+
 pub struct LCDPalette {
     palette: [u8; 4],
 }
@@ -121,18 +139,22 @@ impl LCDPalette {
         }
     }
 
-    fn get_color(&self, idx: usize) -> Rgba<u8> {
-        LCDPalette::get_color_absolute(self.palette[idx] as usize)
+    fn get_color(&self, idx: usize, shades: Shades) -> Rgba<u8> {
+        LCDPalette::get_color_absolute(self.palette[idx] as usize, shades)
     }
 
-    fn get_color_absolute(idx: usize) -> Rgba<u8> {
-        match idx {
-            3 => Rgba::from_channels(0, 0, 0, 255),
-            2 => Rgba::from_channels(69, 69, 69, 255),
-            1 => Rgba::from_channels(152, 152, 152, 255),
-            0 => Rgba::from_channels(240, 240, 240, 255),
+    fn get_color_absolute(idx: usize, shades: Shades) -> Rgba<u8> {
+        let level = match (shades, idx) {
+            (_, 3) => 0,
+            (Shades::Default, 2) => 69,
+            (Shades::Default, 1) => 152,
+            (Shades::Default, 0) => 240,
+            (Shades::Grayscale, 2) => 0x55,
+            (Shades::Grayscale, 1) => 0xaa,
+            (Shades::Grayscale, 0) => 0xff,
             _ => panic!("Invalid level"),
-        }
+        };
+        Rgba::from_channels(level, level, level, 255)
     }
 
     pub fn get_background_color() -> Rgba<u8> {
@@ -152,27 +174,16 @@ fn get_level_in_tile(x: u8, y: u8, tile_data: &[u8]) -> u8 {
     (bit1 << 1) | bit2
 }
 
-fn get_tile(x: u8, y: u8, ram: &[u8], lcd_settings: LCDCValues) -> u8 {
+fn get_tile(x: u8, y: u8, ram: &[u8], tile_map_addr: usize) -> u8 {
     let tile_x = x / 8;
     let tile_y = y / 8;
     let tile_idx = tile_x as u16 + tile_y as u16 * TILE_RESOLUTION_W as u16;
 
-    ram[lcd_settings.tile_map_addr() + tile_idx as usize]
+    ram[tile_map_addr + tile_idx as usize]
 }
 
-fn get_tile_color_idx(
-    x: u8,
-    y: u8,
-    tile_id: u8,
-    ram: &[u8],
-    window: bool,
-    lcd_settings: LCDCValues,
-) -> u8 {
-    let (base_addr, addressing) = if window {
-        lcd_settings.window_tile_data_addr_and_addressing()
-    } else {
-        lcd_settings.tile_data_addr_and_addressing()
-    };
+fn get_tile_color_idx(x: u8, y: u8, tile_id: u8, ram: &[u8], lcd_settings: LCDCValues) -> u8 {
+    let (base_addr, addressing) = lcd_settings.tile_data_addr_and_addressing();
 
     //some banks use signed addressing, for no good reason at all
     let signed_id = unsafe {
@@ -191,7 +202,61 @@ fn get_tile_color_idx(
     get_level_in_tile(inner_x, inner_y, tile_data)
 }
 
-#[derive(Eq, PartialEq)]
+//the sprite pixel at x, y: its color index and its attributes. Only the first 10 sprites
+//on the line are drawn, where they overlap the one with the lowest X wins
+fn get_sprite_pixel(x: u8, y: u8, ram: &[u8], lcd_settings: LCDCValues) -> Option<(u8, u8)> {
+    let height = lcd_settings.obj_height() as i16;
+    let (x, y) = (x as i16, y as i16);
+
+    let mut on_line: Vec<&[u8]> = ram[address::SPRITE_ATTRIBUTE_TABLE]
+        .chunks(SPRITE_SIZE_BYTES)
+        .take(MAX_SPRITES as usize)
+        .filter(|sprite| {
+            let top = sprite[0] as i16 - 16;
+            y >= top && y < top + height
+        })
+        .take(MAX_SPRITES_PER_LINE as usize)
+        .collect();
+    //the sort is stable, so the first one in OAM wins on the same X
+    on_line.sort_by_key(|sprite| sprite[1]);
+
+    for sprite in on_line {
+        let left = sprite[1] as i16 - 8;
+        if x < left || x >= left + MAX_SPRITE_SIZE_W as i16 {
+            continue;
+        }
+
+        let attributes = sprite[3];
+        let mut row = y - (sprite[0] as i16 - 16);
+        if attributes.get_bit(6) {
+            row = height - 1 - row;
+        }
+        let mut column = x - left;
+        if attributes.get_bit(5) {
+            column = 7 - column;
+        }
+
+        //8x16 sprites take two tiles, the lowest bit of the tile ID is ignored
+        let tile_id = if height == 16 {
+            sprite[2] & 0xfe
+        } else {
+            sprite[2]
+        };
+        let tile_data_start = address::UNSIGNED_TILE_DATA_TABLE.start
+            + tile_id as usize * TILE_SIZE_BYTES
+            + (row / 8) as usize * TILE_SIZE_BYTES;
+        let tile_data = &ram[tile_data_start..tile_data_start + TILE_SIZE_BYTES];
+
+        //color 0 is transparent, the next sprite can still show through
+        let idx = get_level_in_tile(column as u8, (row % 8) as u8, tile_data);
+        if idx != 0 {
+            return Some((idx, attributes));
+        }
+    }
+    None
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
 enum State {
     OAMSearch,
     PixelTransfer,
@@ -200,13 +265,41 @@ enum State {
     Off,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PPUState {
+    screen_buffer: Vec<u8>,
+
+    next_scanline_change_clock: u64,
+    state: State,
+    current_pixel_x: u8,
+    window_line: u8,
+    window_on_line: bool,
+}
+
+impl PPUState {
+    //what load_state checks before it changes anything
+    pub fn validate(&self) -> io::Result<()> {
+        if self.screen_buffer.len() != RESOLUTION_W as usize * RESOLUTION_H as usize * 4 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The save state screen has the wrong size",
+            ));
+        }
+        Ok(())
+    }
+}
+
 #[allow(non_snake_case)]
 pub struct PPU {
     pub screen_buffer: RgbaImage,
+    pub shades: Shades,
 
     next_scanline_change_clock: u64,
     state: State,
     current_pixel_x: u8,
+    //the window has a line counter of its own, it only goes on when the window was drawn
+    window_line: u8,
+    window_on_line: bool,
 }
 
 impl PPU {
@@ -219,38 +312,98 @@ impl PPU {
             next_scanline_change_clock: 0,
             state: State::Off,
             current_pixel_x: 0,
+            window_line: 0,
+            window_on_line: false,
             screen_buffer: img,
+            shades: Shades::Default,
         }
     }
 
-    fn render_pixel(&self, current_pixel_y: u8, ram: &[u8], dma_in_progress: bool) -> Rgba<u8> {
-        let scroll_x = ram[address::SCX_REGISTER];
-        let scroll_y = ram[address::SCY_REGISTER];
+    pub fn save_state(&self) -> PPUState {
+        PPUState {
+            screen_buffer: self.screen_buffer.clone().into_raw(),
+            next_scanline_change_clock: self.next_scanline_change_clock,
+            state: self.state,
+            current_pixel_x: self.current_pixel_x,
+            window_line: self.window_line,
+            window_on_line: self.window_on_line,
+        }
+    }
 
+    pub fn load_state(&mut self, state: &PPUState) -> io::Result<()> {
+        state.validate()?;
+
+        self.screen_buffer = RgbaImage::from_raw(
+            RESOLUTION_W as u32,
+            RESOLUTION_H as u32,
+            state.screen_buffer.clone(),
+        )
+        .unwrap();
+
+        self.next_scanline_change_clock = state.next_scanline_change_clock;
+        self.state = state.state;
+        self.current_pixel_x = state.current_pixel_x;
+        self.window_line = state.window_line;
+        self.window_on_line = state.window_on_line;
+        Ok(())
+    }
+
+    fn in_window(&self, current_pixel_y: u8, ram: &[u8]) -> bool {
         let lcd_settings = LCDCValues::from_ram(ram);
 
-        let x = self.current_pixel_x.wrapping_add(scroll_x);
-        let y = current_pixel_y.wrapping_add(scroll_y);
+        //WX is the left edge plus 7. Without the background there's no window either
+        lcd_settings.bg_on()
+            && lcd_settings.windowing_on()
+            && current_pixel_y >= ram[address::WY_REGISTER]
+            && self.current_pixel_x as u16 + 7 >= ram[address::WX_REGISTER] as u16
+    }
 
-        assert!(lcd_settings.windowing_on() == false);
+    fn render_pixel(
+        &self,
+        current_pixel_y: u8,
+        ram: &[u8],
+        window: bool,
+        dma_in_progress: bool,
+    ) -> Rgba<u8> {
+        let lcd_settings = LCDCValues::from_ram(ram);
+
+        let (x, y, tile_map_addr) = if window {
+            let x = (self.current_pixel_x as u16 + 7 - ram[address::WX_REGISTER] as u16) as u8;
+            (x, self.window_line, lcd_settings.window_tile_map_addr())
+        } else {
+            let scroll_x = ram[address::SCX_REGISTER];
+            let scroll_y = ram[address::SCY_REGISTER];
+            let x = self.current_pixel_x.wrapping_add(scroll_x);
+            let y = current_pixel_y.wrapping_add(scroll_y);
+            (x, y, lcd_settings.tile_map_addr())
+        };
 
-        let mut color = LCDPalette::get_color_absolute(0);
+        let mut color = LCDPalette::get_color_absolute(0, self.shades);
+        let mut bg_idx = 0;
 
         if lcd_settings.bg_on() {
             let palette = LCDPalette::from_register(ram[address::BGP_REGISTER]);
 
-            let tile_id = get_tile(x, y, ram, lcd_settings);
-            let idx = get_tile_color_idx(x, y, tile_id, ram, false, lcd_settings);
-            color = palette.get_color(idx as usize);
+            let tile_id = get_tile(x, y, ram, tile_map_addr);
+            bg_idx = get_tile_color_idx(x, y, tile_id, ram, lcd_settings);
+            color = palette.get_color(bg_idx as usize, self.shades);
         }
 
         //sprites don't draw during DMA
         if lcd_settings.obj_on() && !dma_in_progress {
-            let _palette0 = LCDPalette::from_register(ram[address::OBP0_REGISTER]);
-            let _palette1 = LCDPalette::from_register(ram[address::OBP1_REGISTER]);
-
-            assert!(lcd_settings.double_obj() == false, "Not implemented yet");
-            // panic!("Not implemented yet");
+            let sprite = get_sprite_pixel(self.current_pixel_x, current_pixel_y, ram, lcd_settings);
+            if let Some((idx, attributes)) = sprite {
+                //with bit 7 set, the sprite only shows over background color 0
+                if !attributes.get_bit(7) || bg_idx == 0 {
+                    let palette_register = if attributes.get_bit(4) {
+                        address::OBP1_REGISTER
+                    } else {
+                        address::OBP0_REGISTER
+                    };
+                    let palette = LCDPalette::from_register(ram[palette_register]);
+                    color = palette.get_color(idx as usize, self.shades);
+                }
+            }
         }
 
         //TODO also do color mixing using alpha
@@ -258,10 +411,14 @@ impl PPU {
         color
     }
 
-    pub fn tick(&mut self, cpu: &mut CPU, current_clock: u64) {
+    //the CPU runs this every clock, along with the DMA and the timers.
+    //returns true when VBlank starts
+    pub fn tick(&mut self, ram: &mut [u8], dma_in_progress: bool, current_clock: u64) -> bool {
+        let mut vblank = false;
+
         //state transition
-        let lcd_control = LCDCValues::from_ram(&cpu.RAM);
-        let current_pixel_y = cpu.RAM[address::LY_REGISTER];
+        let lcd_control = LCDCValues::from_ram(ram);
+        let current_pixel_y = ram[address::LY_REGISTER];
 
         let new_state = match self.state {
             State::OAMSearch => {
@@ -273,7 +430,9 @@ impl PPU {
             }
             State::PixelTransfer => {
                 // TODO emulate clock accurate FIFO?
-                let pixel = self.render_pixel(current_pixel_y, &cpu.RAM, cpu.is_dma_mode());
+                let window = self.in_window(current_pixel_y, ram);
+                self.window_on_line |= window;
+                let pixel = self.render_pixel(current_pixel_y, ram, window, dma_in_progress);
 
                 self.screen_buffer.put_pixel(
                     self.current_pixel_x as u32,
@@ -305,7 +464,7 @@ impl PPU {
             State::VBlank => {
                 if current_clock == self.next_scanline_change_clock {
                     if lcd_control.lcd_on() {
-                        let y = &mut cpu.RAM[address::LY_REGISTER];
+                        let y = &mut ram[address::LY_REGISTER];
                         *y += 1;
 
                         if *y == LY_VALUES_COUNT {
@@ -339,14 +498,18 @@ impl PPU {
             // state end
             match self.state {
                 State::OAMSearch => {}
-                State::PixelTransfer => {}
+                State::PixelTransfer => {
+                    if self.window_on_line {
+                        self.window_line += 1;
+                    }
+                }
                 State::HBlank => {
-                    let y = &mut cpu.RAM[address::LY_REGISTER];
+                    let y = &mut ram[address::LY_REGISTER];
                     *y += 1;
                 }
                 State::VBlank => {}
                 State::Off => {
-                    cpu.RAM[address::LY_REGISTER] = 0;
+                    ram[address::LY_REGISTER] = 0;
                     self.current_pixel_x = 0;
                 }
             }
@@ -356,6 +519,11 @@ impl PPU {
                     self.next_scanline_change_clock =
                         current_clock + OAM_SEARCH_PHASE_DURATION_CLOCKS;
                     self.current_pixel_x = 0;
+                    self.window_on_line = false;
+                    //a new frame
+                    if ram[address::LY_REGISTER] == 0 {
+                        self.window_line = 0;
+                    }
                 }
                 State::PixelTransfer => {
                     self.next_scanline_change_clock =
@@ -366,7 +534,7 @@ impl PPU {
                 }
                 State::VBlank => {
                     self.next_scanline_change_clock = current_clock + V_BLANK_PHASE_DURATION_CLOCKS;
-                    cpu.request_vblank();
+                    vblank = true;
                 }
                 State::Off => {
                     // blank the screen
@@ -379,5 +547,7 @@ impl PPU {
 
             self.state = new_state;
         }
+
+        vblank
     }
 }
@@ -0,0 +1,294 @@
+extern crate std;
+
+use bit_field::BitField;
+use image::Pixel;
+use image::Rgba;
+use image::RgbaImage;
+use serial::SerialDevice;
+use std::io;
+use std::path::PathBuf;
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+//what the printer answers during the first byte after a packet
+const DEVICE_ID: u8 = 0x81;
+
+const COMMAND_INIT: u8 = 0x01;
+const COMMAND_PRINT: u8 = 0x02;
+const COMMAND_DATA: u8 = 0x04;
+const COMMAND_STATUS: u8 = 0x0f;
+
+const STATUS_CHECKSUM_ERROR: usize = 0;
+const STATUS_BUSY: usize = 1;
+const STATUS_UNPROCESSED_DATA: usize = 3;
+
+//the paper is 160 pixels wide, 20 tiles of 8x8
+const TILES_PER_ROW: usize = 20;
+const TILE_SIZE_BYTES: usize = 8 * 2;
+const ROW_SIZE_BYTES: usize = TILES_PER_ROW * TILE_SIZE_BYTES;
+
+//games poll the status until the printer stops being busy
+const BUSY_STATUS_POLLS: u8 = 4;
+
+#[derive(Clone, Copy)]
+enum PacketState {
+    Magic(usize),
+    Command,
+    Compression,
+    LengthLo,
+    LengthHi,
+    Data,
+    ChecksumLo,
+    ChecksumHi,
+    DeviceId,
+    Status,
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    //a control byte with the top bit set repeats the next byte (n & 0x7f) + 2 times,
+    //otherwise n + 1 bytes are copied over as they are
+    let mut out = vec![];
+    let mut idx = 0;
+    while idx < data.len() {
+        let control = data[idx] as usize;
+        idx += 1;
+
+        if control.get_bit(7) {
+            if let Some(&byte) = data.get(idx) {
+                for _ in 0..(control & 0x7f) + 2 {
+                    out.push(byte);
+                }
+            }
+            idx += 1;
+        } else {
+            let end = std::cmp::min(idx + control + 1, data.len());
+            out.extend_from_slice(&data[idx..end]);
+            idx = end;
+        }
+    }
+    out
+}
+
+fn shade(level: u8) -> Rgba<u8> {
+    let value = 255 - level * 85;
+    Rgba::from_channels(value, value, value, 255)
+}
+
+fn render(data: &[u8], palette: u8) -> RgbaImage {
+    let rows = data.len() / ROW_SIZE_BYTES;
+    let mut img = RgbaImage::new(TILES_PER_ROW as u32 * 8, rows as u32 * 8);
+
+    for (tile_idx, tile) in data.chunks(TILE_SIZE_BYTES).take(rows * TILES_PER_ROW).enumerate() {
+        let tile_x = (tile_idx % TILES_PER_ROW) * 8;
+        let tile_y = (tile_idx / TILES_PER_ROW) * 8;
+
+        for y in 0..8 {
+            let lo = tile[y * 2];
+            let hi = tile[y * 2 + 1];
+            for x in 0..8 {
+                let color = ((hi.get_bit(7 - x) as u8) << 1) | lo.get_bit(7 - x) as u8;
+                let level = palette.get_bits(color as usize * 2..color as usize * 2 + 2);
+                img.put_pixel((tile_x + x) as u32, (tile_y + y) as u32, shade(level));
+            }
+        }
+    }
+    img
+}
+
+pub struct Printer {
+    output_dir: PathBuf,
+    pub pages_printed: usize,
+    error: Option<io::Error>,
+
+    //what to shift out during the next byte
+    reply: u8,
+
+    state: PacketState,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+
+    image_data: Vec<u8>,
+    status: u8,
+    busy_polls: u8,
+}
+
+impl Printer {
+    pub fn new(output_dir: PathBuf) -> Self {
+        Printer {
+            output_dir: output_dir,
+            pages_printed: 0,
+            error: None,
+            reply: 0,
+            state: PacketState::Magic(0),
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: vec![],
+            checksum: 0,
+            received_checksum: 0,
+            image_data: vec![],
+            status: 0,
+            busy_polls: 0,
+        }
+    }
+
+    //returns what to shift out during the next byte
+    fn receive_byte(&mut self, byte: u8) -> io::Result<u8> {
+        let mut reply = 0;
+
+        self.state = match self.state {
+            PacketState::Magic(idx) => {
+                if byte == MAGIC[idx] && idx + 1 == MAGIC.len() {
+                    PacketState::Command
+                } else if byte == MAGIC[idx] {
+                    PacketState::Magic(idx + 1)
+                } else if byte == MAGIC[0] {
+                    PacketState::Magic(1)
+                } else {
+                    PacketState::Magic(0)
+                }
+            }
+            PacketState::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                PacketState::Compression
+            }
+            PacketState::Compression => {
+                self.compressed = byte != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthLo
+            }
+            PacketState::LengthLo => {
+                self.length = byte as usize;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthHi
+            }
+            PacketState::LengthHi => {
+                self.length |= (byte as usize) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.data.clear();
+                if self.length == 0 {
+                    PacketState::ChecksumLo
+                } else {
+                    PacketState::Data
+                }
+            }
+            PacketState::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.data.len() == self.length {
+                    PacketState::ChecksumLo
+                } else {
+                    PacketState::Data
+                }
+            }
+            PacketState::ChecksumLo => {
+                self.received_checksum = byte as u16;
+                PacketState::ChecksumHi
+            }
+            PacketState::ChecksumHi => {
+                self.received_checksum |= (byte as u16) << 8;
+                reply = DEVICE_ID;
+                PacketState::DeviceId
+            }
+            PacketState::DeviceId => {
+                self.run_command()?;
+                reply = self.status;
+                PacketState::Status
+            }
+            PacketState::Status => PacketState::Magic(0),
+        };
+
+        Ok(reply)
+    }
+
+    fn run_command(&mut self) -> io::Result<()> {
+        if self.checksum != self.received_checksum {
+            self.status.set_bit(STATUS_CHECKSUM_ERROR, true);
+            return Ok(());
+        }
+        self.status.set_bit(STATUS_CHECKSUM_ERROR, false);
+
+        match self.command {
+            COMMAND_INIT => {
+                self.image_data.clear();
+                self.status = 0;
+                self.busy_polls = 0;
+            }
+            COMMAND_DATA => {
+                //an empty data packet just marks the end of the image
+                if self.compressed {
+                    let data = decompress(&self.data);
+                    self.image_data.extend_from_slice(&data);
+                } else {
+                    self.image_data.extend_from_slice(&self.data);
+                }
+                let unprocessed = !self.image_data.is_empty();
+                self.status.set_bit(STATUS_UNPROCESSED_DATA, unprocessed);
+            }
+            COMMAND_PRINT => {
+                //sheets, margins, palette, exposure
+                let palette = match self.data.get(2) {
+                    //0 means the default palette
+                    Some(&0) | None => 0xe4,
+                    Some(&palette) => palette,
+                };
+                self.print(palette)?;
+
+                self.status.set_bit(STATUS_UNPROCESSED_DATA, false);
+                self.status.set_bit(STATUS_BUSY, true);
+                self.busy_polls = BUSY_STATUS_POLLS;
+            }
+            COMMAND_STATUS => {
+                if self.busy_polls > 0 {
+                    self.busy_polls -= 1;
+                    if self.busy_polls == 0 {
+                        self.status.set_bit(STATUS_BUSY, false);
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn print(&mut self, palette: u8) -> io::Result<()> {
+        if self.image_data.len() < ROW_SIZE_BYTES {
+            //nothing to put on paper
+            self.image_data.clear();
+            return Ok(());
+        }
+
+        let img = render(&self.image_data, palette);
+        self.image_data.clear();
+
+        let path = self
+            .output_dir
+            .join(format!("print_{:04}.png", self.pages_printed));
+        self.pages_printed += 1;
+
+        img.save(path)
+    }
+}
+
+//the printer never drives the clock, the gameboy is always the master
+impl SerialDevice for Printer {
+    fn exchange_byte(&mut self, out: u8) -> u8 {
+        let reply = self.reply;
+        self.reply = match self.receive_byte(out) {
+            Ok(reply) => reply,
+            Err(error) => {
+                self.error = Some(error);
+                0
+            }
+        };
+        reply
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
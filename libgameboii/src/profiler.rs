@@ -0,0 +1,237 @@
+extern crate std;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use symbols::Symbols;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Routine {
+    pub bank: usize,
+    pub addr: u16,
+}
+
+impl Routine {
+    fn name(&self, symbols: Option<&Symbols>) -> String {
+        match symbols.and_then(|symbols| symbols.label(self.bank, self.addr)) {
+            Some(label) => label.to_owned(),
+            None => format!("{:02x}:{:04x}", self.bank, self.addr),
+        }
+    }
+}
+
+//a routine that was called and hasn't returned yet
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    pub routine: Routine,
+    //the CALL or RST, or the instruction an interrupt came after
+    pub call_site: u16,
+    //where the return address is
+    sp: u16,
+    start_cycles: u64,
+}
+
+enum StackChange {
+    Enter(Routine, u16, u16),
+    Exit(u16),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RoutineStats {
+    pub calls: u64,
+    //cycles spent in the routine itself
+    pub exclusive: u64,
+    //cycles spent in the routine and everything it called
+    pub inclusive: u64,
+}
+
+//keeps a shadow call stack from the CALLs, RSTs, interrupts and RETs the CPU runs,
+//and counts the cycles spent in each routine
+pub struct Profiler {
+    frames: Vec<Frame>,
+    total_cycles: u64,
+    routines: BTreeMap<Routine, RoutineStats>,
+
+    //cycles for each distinct call stack, for flame graphs. The empty stack is
+    //whatever runs outside of any call
+    stack_ids: HashMap<Vec<Routine>, usize>,
+    stacks: Vec<(Vec<Routine>, u64)>,
+    current_stack: usize,
+
+    //calls and returns take effect once the instruction's cycles are counted,
+    //so a CALL counts for the caller and a RET for the callee
+    pending: Vec<StackChange>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        let mut stack_ids = HashMap::new();
+        stack_ids.insert(vec![], 0);
+
+        Profiler {
+            frames: vec![],
+            total_cycles: 0,
+            routines: BTreeMap::new(),
+            stack_ids: stack_ids,
+            stacks: vec![(vec![], 0)],
+            current_stack: 0,
+            pending: vec![],
+        }
+    }
+
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    //the stats so far, counting the routines that are still running
+    pub fn stats(&self, routine: Routine) -> Option<RoutineStats> {
+        self.routines.get(&routine).map(|stats| RoutineStats {
+            inclusive: stats.inclusive + self.running_cycles(routine),
+            ..*stats
+        })
+    }
+
+    //the cycles of the outermost call of routine that hasn't returned yet
+    fn running_cycles(&self, routine: Routine) -> u64 {
+        self.frames
+            .iter()
+            .find(|frame| frame.routine == routine)
+            .map(|frame| self.total_cycles - frame.start_cycles)
+            .unwrap_or(0)
+    }
+
+    fn update_stack_id(&mut self) {
+        let stack: Vec<Routine> = self.frames.iter().map(|frame| frame.routine).collect();
+        let next_id = self.stacks.len();
+        let id = *self.stack_ids.entry(stack.clone()).or_insert(next_id);
+        if id == next_id {
+            self.stacks.push((stack, 0));
+        }
+        self.current_stack = id;
+    }
+
+    //sp is the stack pointer after pushing the return address
+    pub fn enter(&mut self, routine: Routine, call_site: u16, sp: u16) {
+        self.pending
+            .push(StackChange::Enter(routine, call_site, sp));
+    }
+
+    //sp is the stack pointer before popping the return address
+    pub fn exit(&mut self, sp: u16) {
+        self.pending.push(StackChange::Exit(sp));
+    }
+
+    fn push_frame(&mut self, routine: Routine, call_site: u16, sp: u16) {
+        self.routines.entry(routine).or_default().calls += 1;
+        self.frames.push(Frame {
+            routine: routine,
+            call_site: call_site,
+            sp: sp,
+            start_cycles: self.total_cycles,
+        });
+        self.update_stack_id();
+    }
+
+    //code that drops return addresses and jumps away instead unwinds more than one
+    //frame at the next return
+    fn pop_frames(&mut self, sp: u16) {
+        let mut popped = false;
+        while let Some(frame) = self.frames.last().cloned() {
+            if frame.sp > sp {
+                break;
+            }
+            self.frames.pop();
+            popped = true;
+
+            //recursive calls are already counted by the outermost one
+            if self
+                .frames
+                .iter()
+                .all(|outer| outer.routine != frame.routine)
+            {
+                let stats = self.routines.get_mut(&frame.routine).unwrap();
+                stats.inclusive += self.total_cycles - frame.start_cycles;
+            }
+        }
+
+        if popped {
+            self.update_stack_id();
+        }
+    }
+
+    pub fn add_cycles(&mut self, cycles: u64) {
+        self.total_cycles += cycles;
+        self.stacks[self.current_stack].1 += cycles;
+        if let Some(frame) = self.frames.last() {
+            self.routines.get_mut(&frame.routine).unwrap().exclusive += cycles;
+        }
+
+        for change in std::mem::take(&mut self.pending) {
+            match change {
+                StackChange::Enter(routine, call_site, sp) => {
+                    self.push_frame(routine, call_site, sp)
+                }
+                StackChange::Exit(sp) => self.pop_frames(sp),
+            }
+        }
+    }
+
+    //one line per routine, the most expensive first
+    pub fn write_flat<W: Write>(&self, out: &mut W, symbols: Option<&Symbols>) -> io::Result<()> {
+        let mut routines: Vec<(Routine, RoutineStats)> = self
+            .routines
+            .keys()
+            .map(|routine| (*routine, self.stats(*routine).unwrap()))
+            .collect();
+        routines.sort_by_key(|&(_, stats)| std::cmp::Reverse(stats.exclusive));
+
+        let percent =
+            |cycles: u64| cycles as f64 * 100.0 / std::cmp::max(self.total_cycles, 1) as f64;
+
+        writeln!(out, "total cycles: {}", self.total_cycles)?;
+        writeln!(
+            out,
+            "{:>12} {:>7} {:>12} {:>7} {:>9}  routine",
+            "exclusive", "%", "inclusive", "%", "calls"
+        )?;
+        for (routine, stats) in routines {
+            writeln!(
+                out,
+                "{:>12} {:>6.2}% {:>12} {:>6.2}% {:>9}  {}",
+                stats.exclusive,
+                percent(stats.exclusive),
+                stats.inclusive,
+                percent(stats.inclusive),
+                stats.calls,
+                routine.name(symbols)
+            )?;
+        }
+        Ok(())
+    }
+
+    //the collapsed stack format of flamegraph.pl: "outer;inner;innermost cycles"
+    pub fn write_collapsed<W: Write>(
+        &self,
+        out: &mut W,
+        symbols: Option<&Symbols>,
+    ) -> io::Result<()> {
+        for &(ref stack, cycles) in &self.stacks {
+            if cycles == 0 {
+                continue;
+            }
+
+            let mut line = String::from("root");
+            for routine in stack {
+                line += ";";
+                line += &routine.name(symbols);
+            }
+            writeln!(out, "{} {}", line, cycles)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+extern crate std;
+
+use address;
+use std::ops::Range;
+
+//where games keep their variables
+const SEARCHED: [Range<usize>; 3] = [address::EXTERNAL_RAM, address::WORK_RAM, address::HIGH_RAM];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    //compared to the last snapshot
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    //the byte is this now
+    Value(u8),
+}
+
+impl Filter {
+    fn keeps(&self, old: u8, new: u8) -> bool {
+        match *self {
+            Filter::Equal => new == old,
+            Filter::Changed => new != old,
+            Filter::Increased => new > old,
+            Filter::Decreased => new < old,
+            Filter::Value(value) => new == value,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Candidate {
+    pub addr: u16,
+    //the value in the last snapshot
+    pub value: u8,
+}
+
+//narrows down where a variable lives: snapshot the RAM, play a bit, keep the
+//bytes that changed the way the variable did, repeat
+pub struct RamSearch {
+    candidates: Vec<Candidate>,
+}
+
+impl RamSearch {
+    //every byte of RAM is a candidate at first
+    pub fn new(ram: &[u8]) -> Self {
+        let candidates = SEARCHED
+            .iter()
+            .flat_map(|range| range.clone())
+            .map(|addr| Candidate {
+                addr: addr as u16,
+                value: ram[addr],
+            })
+            .collect();
+
+        RamSearch {
+            candidates: candidates,
+        }
+    }
+
+    //keeps the candidates that pass, and takes the new snapshot. Returns how many are left
+    pub fn filter(&mut self, ram: &[u8], filter: Filter) -> usize {
+        self.candidates
+            .retain(|candidate| filter.keeps(candidate.value, ram[candidate.addr as usize]));
+        for candidate in &mut self.candidates {
+            candidate.value = ram[candidate.addr as usize];
+        }
+        self.candidates.len()
+    }
+
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+}
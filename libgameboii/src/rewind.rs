@@ -0,0 +1,171 @@
+extern crate std;
+
+use cpu::CPU;
+use ppu::FRAME_CLOCKS;
+use save_state::SaveState;
+use std::collections::VecDeque;
+
+//runs of zeros shorter than this are cheaper to keep in the literals
+const MIN_ZERO_RUN: usize = 4;
+const MAX_RUN: usize = 0xffff;
+
+fn push_u16(out: &mut Vec<u8>, val: usize) {
+    out.push((val >> 8) as u8);
+    out.push(val as u8);
+}
+
+fn read_u16(data: &[u8], idx: usize) -> usize {
+    ((data[idx] as usize) << 8) | data[idx + 1] as usize
+}
+
+fn zero_run(data: &[u8], start: usize) -> usize {
+    data[start..]
+        .iter()
+        .take(MAX_RUN)
+        .take_while(|b| **b == 0)
+        .count()
+}
+
+//the XOR of two snapshots is mostly zeros, so store it as
+//(zeros count, literals count, literals...) chunks
+fn compress(delta: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut idx = 0;
+    while idx < delta.len() {
+        let zeros = zero_run(delta, idx);
+        idx += zeros;
+
+        let start = idx;
+        while idx < delta.len() && idx - start < MAX_RUN && zero_run(delta, idx) < MIN_ZERO_RUN {
+            idx += 1;
+        }
+
+        push_u16(&mut out, zeros);
+        push_u16(&mut out, idx - start);
+        out.extend_from_slice(&delta[start..idx]);
+    }
+    out
+}
+
+fn xor_into(target: &mut [u8], compressed: &[u8]) {
+    let mut pos = 0;
+    let mut idx = 0;
+    while idx < compressed.len() {
+        pos += read_u16(compressed, idx);
+        let literals = read_u16(compressed, idx + 2);
+        idx += 4;
+
+        for byte in &compressed[idx..idx + literals] {
+            target[pos] ^= *byte;
+            pos += 1;
+        }
+        idx += literals;
+    }
+}
+
+//turns a snapshot back into the one taken before it
+struct Delta {
+    len: usize,
+    data: Vec<u8>,
+}
+
+impl Delta {
+    fn between(newer: &[u8], older: &[u8]) -> Self {
+        //the states can change size a bit, pad the shorter one with zeros
+        let len = std::cmp::max(newer.len(), older.len());
+        let xor = (0..len)
+            .map(|idx| newer.get(idx).unwrap_or(&0) ^ older.get(idx).unwrap_or(&0))
+            .collect::<Vec<_>>();
+
+        Delta {
+            len: older.len(),
+            data: compress(&xor),
+        }
+    }
+
+    fn apply(&self, newer: &mut Vec<u8>) {
+        let len = std::cmp::max(newer.len(), self.len);
+        newer.resize(len, 0);
+        xor_into(newer, &self.data);
+        newer.truncate(self.len);
+    }
+}
+
+//keeps the last few seconds of gameplay around, to step back through them.
+//only the newest snapshot is stored whole, older ones are deltas going backwards
+pub struct Rewind {
+    interval_frames: u32,
+    max_snapshots: usize,
+    next_snapshot_clock: u64,
+
+    newest: Option<Vec<u8>>,
+    deltas: VecDeque<Delta>,
+}
+
+impl Rewind {
+    pub fn new(seconds: u32, interval_frames: u32) -> Self {
+        assert!(interval_frames > 0);
+        //the gameboy runs at ~59.7 frames per second
+        let frames = seconds as u64 * ::cpu::MACHINE_HZ / FRAME_CLOCKS;
+
+        Rewind {
+            interval_frames: interval_frames,
+            max_snapshots: (frames / interval_frames as u64) as usize,
+            next_snapshot_clock: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    //call it once per clock, before ticking the CPU.
+    //a snapshot is taken at the start of every interval_frames frames
+    pub fn tick(&mut self, cpu: &CPU, current_clock: u64) {
+        if current_clock >= self.next_snapshot_clock && current_clock % FRAME_CLOCKS == 0 {
+            self.push(&SaveState::capture(cpu, current_clock));
+        }
+    }
+
+    pub fn push(&mut self, state: &SaveState) {
+        let mut snapshot = vec![];
+        state.write_to(&mut snapshot).unwrap();
+
+        if let Some(older) = self.newest.take() {
+            self.deltas.push_back(Delta::between(&snapshot, &older));
+            if self.deltas.len() > self.max_snapshots {
+                self.deltas.pop_front();
+            }
+        }
+        self.newest = Some(snapshot);
+        self.next_snapshot_clock = state.current_clock() + self.interval_frames as u64 * FRAME_CLOCKS;
+    }
+
+    //forget everything, eg. after jumping somewhere else with a save state
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.next_snapshot_clock = 0;
+    }
+
+    pub fn snapshot_count(&self) -> usize {
+        self.newest.iter().count() + self.deltas.len()
+    }
+
+    //steps back at least this many frames from the newest snapshot, or as far as it goes.
+    //the newer snapshots are dropped, so the game can take a different branch from there
+    pub fn rewind(&mut self, frames: u32) -> Option<SaveState> {
+        let steps = std::cmp::max(1, (frames + self.interval_frames - 1) / self.interval_frames);
+
+        let mut snapshot = self.newest.take()?;
+        for _ in 0..steps {
+            match self.deltas.pop_back() {
+                Some(delta) => delta.apply(&mut snapshot),
+                None => break,
+            }
+        }
+
+        let state = SaveState::read_from(&mut &snapshot[..]).unwrap();
+        self.newest = Some(snapshot);
+        self.next_snapshot_clock = state.current_clock() + self.interval_frames as u64 * FRAME_CLOCKS;
+        Some(state)
+    }
+}
@@ -0,0 +1,116 @@
+extern crate std;
+
+use bincode;
+use cpu::{CPUState, CPU};
+use ppu::PPUState;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: [u8; 4] = *b"GBii";
+//bump it whenever the layout of the states changes
+const VERSION: u16 = 6;
+
+fn invalid_data<E: ToString>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Machine {
+    cpu: CPUState,
+    ppu: PPUState,
+    current_clock: u64,
+}
+
+//a snapshot of the whole machine. On file it's a header with the format version and
+//the checksum of the ROM it was taken with, followed by the bincode encoded machine
+#[derive(Clone)]
+pub struct SaveState {
+    rom_checksum: u32,
+    machine: Machine,
+}
+
+impl SaveState {
+    pub fn capture(cpu: &CPU, current_clock: u64) -> Self {
+        SaveState {
+            rom_checksum: cpu.rom_checksum(),
+            machine: Machine {
+                cpu: cpu.save_state(),
+                ppu: cpu.ppu.save_state(),
+                current_clock: current_clock,
+            },
+        }
+    }
+
+    //returns the clock the state was taken at
+    pub fn restore(&self, cpu: &mut CPU) -> io::Result<u64> {
+        if self.rom_checksum != cpu.rom_checksum() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The save state was taken with a different ROM",
+            ));
+        }
+
+        //check both halves first, so a bad state doesn't leave a half restored machine
+        self.machine.cpu.validate()?;
+        self.machine.ppu.validate()?;
+        cpu.load_state(&self.machine.cpu)?;
+        cpu.ppu.load_state(&self.machine.ppu)?;
+        Ok(self.machine.current_clock)
+    }
+
+    pub fn current_clock(&self) -> u64 {
+        self.machine.current_clock
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(&MAGIC)?;
+        out.write_all(&[(VERSION >> 8) as u8, VERSION as u8])?;
+        out.write_all(&[
+            (self.rom_checksum >> 24) as u8,
+            (self.rom_checksum >> 16) as u8,
+            (self.rom_checksum >> 8) as u8,
+            self.rom_checksum as u8,
+        ])?;
+
+        bincode::serialize_into(out, &self.machine).map_err(invalid_data)
+    }
+
+    pub fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let mut header = [0; 10];
+        input.read_exact(&mut header)?;
+
+        if header[0..4] != MAGIC {
+            return Err(invalid_data("Not a save state"));
+        }
+
+        let version = ((header[4] as u16) << 8) | header[5] as u16;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported save state version {}",
+                version
+            )));
+        }
+
+        let rom_checksum = header[6..10]
+            .iter()
+            .fold(0, |sum, b| (sum << 8) | *b as u32);
+        let machine = bincode::deserialize_from(input).map_err(invalid_data)?;
+
+        Ok(SaveState {
+            rom_checksum: rom_checksum,
+            machine: machine,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: &P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_to(&mut out)?;
+        out.flush()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: &P) -> io::Result<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+}
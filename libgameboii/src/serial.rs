@@ -0,0 +1,54 @@
+extern crate std;
+
+use std::io;
+use std::io::Write;
+
+//whatever is plugged in the other end of the serial port
+pub trait SerialDevice {
+    //a byte is about to be shifted out on the gameboy's internal clock.
+    //returns the byte that will be shifted in
+    fn exchange_byte(&mut self, _out: u8) -> u8 {
+        0xff
+    }
+
+    //the gameboy clocked a bit out on its internal clock. Returns the bit shifted in,
+    //or None for devices that only deal with whole bytes
+    fn exchange_bit(&mut self, _out: bool) -> Option<bool> {
+        None
+    }
+
+    //called every clock with the bit on the gameboy's out line, or None when
+    //it isn't waiting for an external clock. Return a bit to pulse the clock and shift it in
+    fn external_clock(&mut self, _out: Option<bool>) -> Option<bool> {
+        None
+    }
+
+    //devices that can fail (a closed socket, a full disk...) report it here
+    fn take_error(&mut self) -> Option<io::Error> {
+        None
+    }
+}
+
+//nothing plugged in, the line just reads high
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {}
+
+//prints whatever the game sends
+pub struct TextOutput<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TextOutput<W> {
+    pub fn new(out: W) -> Self {
+        TextOutput { out: out }
+    }
+}
+
+impl<W: Write> SerialDevice for TextOutput<W> {
+    fn exchange_byte(&mut self, out: u8) -> u8 {
+        //whatever the game might want to say? Let's assume it's chars
+        write!(self.out, "{}", out as char).unwrap();
+        0xff
+    }
+}
@@ -0,0 +1,134 @@
+extern crate std;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+//labels only mean something inside the same area: ROM0, the switchable ROM bank, or RAM
+fn region(addr: u16) -> u8 {
+    if addr < 0x4000 {
+        0
+    } else if addr < 0x8000 {
+        1
+    } else {
+        2
+    }
+}
+
+//only the switchable ROM area has more than one bank
+fn key(bank: usize, addr: u16) -> (usize, u16) {
+    match region(addr) {
+        1 => (bank, addr),
+        _ => (0, addr),
+    }
+}
+
+//labels from a .sym file, as written by RGBDS and no$gmb: "BANK:ADDR Label"
+#[derive(Clone)]
+pub struct Symbols {
+    by_addr: BTreeMap<(usize, u16), String>,
+    by_name: HashMap<String, (usize, u16)>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols {
+            by_addr: BTreeMap::new(),
+            by_name: HashMap::new(),
+        }
+    }
+
+    pub fn parse<R: BufRead>(input: R) -> io::Result<Self> {
+        let mut symbols = Self::new();
+
+        for (idx, line) in input.lines().enumerate() {
+            let line = line?;
+            let line = line.split(';').next().unwrap().trim();
+            //no$gmb has section headers like [labels]
+            if line.is_empty() || line.starts_with('[') {
+                continue;
+            }
+
+            let parsed = {
+                let mut parts = line.split_whitespace();
+                let location = parts.next().unwrap();
+                let name = parts.next();
+                let mut location = location.splitn(2, ':');
+                let bank = location.next().map(|bank| usize::from_str_radix(bank, 16));
+                let addr = location.next().map(|addr| u16::from_str_radix(addr, 16));
+                match (bank, addr, name) {
+                    (Some(Ok(bank)), Some(Ok(addr)), Some(name)) => Some((bank, addr, name)),
+                    _ => None,
+                }
+            };
+
+            match parsed {
+                Some((bank, addr, name)) => symbols.add(bank, addr, name),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid symbol on line {}: {}", idx + 1, line),
+                    ))
+                }
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(BufReader::new(File::open(path)?))
+    }
+
+    //the .sym file next to the ROM, if there's one
+    pub fn for_rom<P: AsRef<Path>>(rom_path: P) -> io::Result<Option<Self>> {
+        let path = rom_path.as_ref().with_extension("sym");
+        if !path.exists() {
+            return Ok(None);
+        }
+        Self::load(path).map(Some)
+    }
+
+    pub fn add(&mut self, bank: usize, addr: u16, name: &str) {
+        //the first label wins, later ones are usually local aliases
+        self.by_addr
+            .entry(key(bank, addr))
+            .or_insert_with(|| name.to_owned());
+        self.by_name.insert(name.to_owned(), (bank, addr));
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    //the bank and address of a label
+    pub fn lookup(&self, name: &str) -> Option<(usize, u16)> {
+        self.by_name.get(name).cloned()
+    }
+
+    //the label right at this address
+    pub fn label(&self, bank: usize, addr: u16) -> Option<&str> {
+        self.by_addr.get(&key(bank, addr)).map(|name| name.as_str())
+    }
+
+    //the closest label at or before the address, as Label or Label+offset
+    pub fn describe(&self, bank: usize, addr: u16) -> Option<String> {
+        let key = key(bank, addr);
+        let (&(label_bank, label_addr), name) = self.by_addr.range(..=key).next_back()?;
+        if label_bank != key.0 || region(label_addr) != region(addr) {
+            return None;
+        }
+
+        match addr - label_addr {
+            0 => Some(name.clone()),
+            offset => Some(format!("{}+{}", name, offset)),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+extern crate std;
+
+use address;
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchCondition {
+    Read,
+    Write,
+    //a write that changes the value
+    Change,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WatchHit {
+    //the instruction doing the access
+    pub pc: u16,
+    pub addr: u16,
+    pub old: u8,
+    pub new: u8,
+    pub access: Access,
+}
+
+struct Watchpoint {
+    range: Range<usize>,
+    condition: WatchCondition,
+}
+
+impl Watchpoint {
+    fn matches(&self, hit: &WatchHit) -> bool {
+        let condition = match self.condition {
+            WatchCondition::Read => hit.access == Access::Read,
+            WatchCondition::Write => hit.access == Access::Write,
+            WatchCondition::Change => hit.access == Access::Write && hit.old != hit.new,
+        };
+        condition && address::in_range(self.range.clone(), hit.addr as usize)
+    }
+}
+
+//the callback returns true to pause the emulation
+pub type WatchCallback = Box<dyn FnMut(&WatchHit) -> bool>;
+
+//memory addresses watched by the host, checked by the CPU on every access
+pub struct Watchpoints {
+    watches: Vec<Watchpoint>,
+    callback: Option<WatchCallback>,
+    paused: bool,
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Watchpoints {
+            watches: vec![],
+            callback: None,
+            paused: false,
+        }
+    }
+
+    pub fn add(&mut self, range: Range<usize>, condition: WatchCondition) {
+        self.watches.push(Watchpoint {
+            range: range,
+            condition: condition,
+        });
+    }
+
+    pub fn remove(&mut self, range: Range<usize>, condition: WatchCondition) {
+        self.watches
+            .retain(|watch| watch.range != range || watch.condition != condition);
+    }
+
+    pub fn clear(&mut self) {
+        self.watches.clear();
+    }
+
+    pub fn set_callback(&mut self, callback: WatchCallback) {
+        self.callback = Some(callback);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    pub fn check(&mut self, hit: WatchHit) {
+        if !self.watches.iter().any(|watch| watch.matches(&hit)) {
+            return;
+        }
+
+        if let Some(ref mut callback) = self.callback {
+            self.paused |= callback(&hit);
+        }
+    }
+
+    //true once after a callback asked to pause
+    pub fn take_pause(&mut self) -> bool {
+        let paused = self.paused;
+        self.paused = false;
+        paused
+    }
+}
@@ -0,0 +1,100 @@
+extern crate image;
+extern crate libgameboii;
+
+mod common;
+
+use common::{boot_rom, dmg_boot_rom, run, run_until_ld_b_b};
+use image::{Rgba, RgbaImage};
+use libgameboii::cpu::CPU;
+use libgameboii::ppu::{Shades, FRAME_CLOCKS};
+use std::panic;
+use std::path::Path;
+
+// dmg-acid2 runs LD B,B once the picture is up
+const TIMEOUT_SECONDS: u64 = 10;
+
+// the mismatched pixels in red, over a faded copy of the reference
+fn diff_image(actual: &RgbaImage, reference: &RgbaImage) -> (RgbaImage, usize) {
+    let mut mismatches = 0;
+    let diff = RgbaImage::from_fn(reference.width(), reference.height(), |x, y| {
+        let expected = reference.get_pixel(x, y);
+        if actual.get_pixel(x, y) == expected {
+            let faded = 0xc0 + expected[0] / 4;
+            Rgba([faded, faded, faded, 0xff])
+        } else {
+            mismatches += 1;
+            Rgba([0xff, 0x00, 0x00, 0xff])
+        }
+    });
+    (diff, mismatches)
+}
+
+#[test]
+fn grayscale_shades() {
+    let code = [
+        0x3e, 0x80, // LD A, 0x80
+        0xe0, 0x40, // LDH (0x40), A: LCD on, background off
+        0x18, 0xfe, // JR -2
+    ];
+    let boot = boot_rom(&code);
+    let cart = vec![0; 0x8000];
+
+    for &(shades, level) in &[(Shades::Default, 240), (Shades::Grayscale, 0xff)] {
+        let mut cpu = CPU::new(&cart, &boot);
+        cpu.ppu.shades = shades;
+
+        run(&mut cpu, 0..2 * FRAME_CLOCKS);
+
+        let white = Rgba([level, level, level, 0xff]);
+        assert!(cpu.ppu.screen_buffer.pixels().all(|pixel| *pixel == white));
+    }
+}
+
+// needs dmg-acid2.gb and reference-dmg.png from https://github.com/mattcurrie/dmg-acid2
+// in tests/dmg-acid2:
+//   curl -L --create-dirs -o tests/dmg-acid2/dmg-acid2.gb \
+//     https://github.com/mattcurrie/dmg-acid2/releases/download/v1.0/dmg-acid2.gb
+//   curl -L --create-dirs -o tests/dmg-acid2/reference-dmg.png \
+//     https://raw.githubusercontent.com/mattcurrie/dmg-acid2/master/img/reference-dmg.png
+// On a mismatch, the screen and a diff are saved next to them
+#[test]
+#[ignore = "needs the dmg-acid2 ROM, and fails until STAT and LYC are emulated"]
+fn dmg_acid2() {
+    let dir = Path::new("tests/dmg-acid2");
+    let rom = libgameboii::open_rom(&dir.join("dmg-acid2.gb"))
+        .expect("dmg-acid2.gb is missing, see the comment above the test");
+    let reference = image::open(dir.join("reference-dmg.png"))
+        .expect("reference-dmg.png is missing, see the comment above the test")
+        .to_rgba();
+    let boot = dmg_boot_rom();
+
+    let mut cpu = CPU::new(&rom, &boot);
+    cpu.ppu.shades = Shades::Grayscale;
+
+    // hardware that isn't there yet panics. What was drawn until then still goes in the diff
+    let finished = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let mut clock = 0;
+        if !run_until_ld_b_b(&mut cpu, &mut clock, TIMEOUT_SECONDS) {
+            return false;
+        }
+
+        // the picture is the same every frame, make sure a whole one is drawn
+        run(&mut cpu, clock..clock + FRAME_CLOCKS);
+        true
+    }));
+
+    let (diff, mismatches) = diff_image(&cpu.ppu.screen_buffer, &reference);
+    if mismatches > 0 {
+        cpu.ppu
+            .screen_buffer
+            .save(dir.join("dmg-acid2.actual.png"))
+            .unwrap();
+        diff.save(dir.join("dmg-acid2.diff.png")).unwrap();
+    }
+    match finished {
+        Ok(true) => {}
+        Ok(false) => panic!("dmg-acid2 didn't finish, see tests/dmg-acid2/dmg-acid2.diff.png"),
+        Err(_) => panic!("the emulator panicked, see tests/dmg-acid2/dmg-acid2.diff.png"),
+    }
+    assert_eq!(mismatches, 0, "see tests/dmg-acid2/dmg-acid2.diff.png");
+}
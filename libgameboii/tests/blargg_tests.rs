@@ -1,9 +1,36 @@
+extern crate image;
 extern crate libgameboii;
 
+mod common;
+
+use common::dmg_boot_rom;
 use libgameboii::cpu::CPU;
-use libgameboii::ppu::PPU;
+use libgameboii::ppu::{FRAME_CLOCKS, PPU};
+use libgameboii::serial::{Disconnected, SerialDevice};
+use std::panic;
 use std::path::Path;
-use std::str;
+
+// the newer tests also report to cartridge RAM. The signature says the rest is valid
+const MEMORY_SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+const MEMORY_STATUS: usize = 0xa000;
+const MEMORY_TEXT: usize = 0xa004;
+const STILL_RUNNING: u8 = 0x80;
+
+// a minute of emulated time, plenty for any of them
+const MAX_FRAMES: u64 = 60 * 60;
+// long enough for the screenshot tests to print their result
+const SCREENSHOT_FRAMES: u64 = 10 * 60;
+
+// the cartridge RAM tests that don't pass yet. They still run, and a test fails once
+// one of them starts passing, so it can come off the list
+const KNOWN_FAILURES: &[&str] = &[
+    "tests/blargg/oam_bug/rom_singles/1-lcd_sync.gb",
+    "tests/blargg/oam_bug/rom_singles/2-causes.gb",
+    "tests/blargg/oam_bug/rom_singles/4-scanline_timing.gb",
+    "tests/blargg/oam_bug/rom_singles/5-timing_bug.gb",
+    "tests/blargg/oam_bug/rom_singles/7-timing_effect.gb",
+    "tests/blargg/oam_bug/rom_singles/8-instr_effect.gb",
+];
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 enum TestState {
@@ -26,10 +53,10 @@ impl TestOut {
     }
 }
 
-impl std::io::Write for TestOut {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+impl SerialDevice for TestOut {
+    fn exchange_byte(&mut self, out: u8) -> u8 {
         // check if the test should stop
-        self.buffer += str::from_utf8(buf).unwrap();
+        self.buffer.push(out as char);
 
         if self.buffer.ends_with("Passed") {
             self.state = TestState::Passed;
@@ -37,43 +64,120 @@ impl std::io::Write for TestOut {
             self.state = TestState::Failed;
         }
 
-        Ok(buf.len())
-    }
-
-    fn flush(&mut self) -> std::io::Result<()> {
-        Ok(())
+        0xff
     }
 }
 
 fn run_test(path: &Path) {
     println!("{:?}", std::env::current_dir().unwrap());
     let rom = libgameboii::open_rom(&path).unwrap();
-    // TODO load from a savestate instead
-    let bootrom = libgameboii::open_rom(&"../ROMs/DMG_ROM.bin").unwrap();
+    let bootrom = dmg_boot_rom();
 
-    let mut ppu = PPU::new();
     let mut cpu = CPU::new(&rom, &bootrom);
 
     let mut current_clock = 0;
 
     let mut serial_out = TestOut::new();
     {
-        let mut update = |cpu: &mut CPU, ppu: &mut PPU| {
+        let mut update = |cpu: &mut CPU| {
             cpu.tick(current_clock, &mut None, &mut serial_out);
-            ppu.tick(cpu, current_clock);
 
             current_clock += 1;
 
             cpu.should_exit == false && serial_out.state == TestState::Running
         };
 
-        while update(&mut cpu, &mut ppu) {}
+        while update(&mut cpu) {}
     }
     println!("{}", serial_out.buffer);
 
     assert_eq!(serial_out.state, TestState::Passed);
 }
 
+// runs the ROM until done says so, or for max_frames
+fn run_frames<F: FnMut(&CPU) -> bool>(rom: &[u8], max_frames: u64, mut done: F) -> PPU {
+    let bootrom = dmg_boot_rom();
+
+    let mut cpu = CPU::new(rom, &bootrom);
+
+    for current_clock in 0..max_frames * FRAME_CLOCKS {
+        cpu.tick(current_clock, &mut None, &mut Disconnected);
+
+        if cpu.should_exit || done(&cpu) {
+            break;
+        }
+    }
+    cpu.ppu
+}
+
+fn memory_test_result(rom: &[u8]) -> Result<(), String> {
+    let mut result = None;
+    let mut started = false;
+    run_frames(rom, MAX_FRAMES, |cpu| {
+        let signed = cpu.RAM[MEMORY_STATUS + 1..MEMORY_TEXT] == MEMORY_SIGNATURE;
+        // the signature can go in before the status says it's running
+        started = started || (signed && cpu.RAM[MEMORY_STATUS] == STILL_RUNNING);
+        if started && cpu.RAM[MEMORY_STATUS] != STILL_RUNNING {
+            let text = cpu.RAM[MEMORY_TEXT..]
+                .iter()
+                .take_while(|c| **c != 0)
+                .map(|c| *c as char)
+                .collect::<String>();
+            result = Some((cpu.RAM[MEMORY_STATUS], text));
+        }
+        result.is_some()
+    });
+
+    match result {
+        Some((0, text)) => {
+            println!("{}", text);
+            Ok(())
+        }
+        Some((status, text)) => Err(format!("failed with status {}: {}", status, text)),
+        None => Err(String::from("didn't finish")),
+    }
+}
+
+fn run_memory_test(path: &Path) {
+    let rom = libgameboii::open_rom(&path).unwrap();
+    // unimplemented hardware panics, which counts as a failure
+    let result = panic::catch_unwind(|| memory_test_result(&rom))
+        .unwrap_or_else(|_| Err(String::from("the emulator panicked")));
+
+    let path = path.to_str().unwrap();
+    match (result, KNOWN_FAILURES.contains(&path)) {
+        (Ok(()), false) | (Err(_), true) => {}
+        (Ok(()), true) => panic!("{} passes now, take it off KNOWN_FAILURES", path),
+        (Err(error), false) => panic!("{} {}", path, error),
+    }
+}
+
+// compares the screen after some frames with path.png. When it's different, or there's
+// no such file yet, the screen is saved to path.actual.png to look at
+fn run_screenshot_test(path: &Path, frames: u64) {
+    let rom = libgameboii::open_rom(&path).unwrap();
+    let ppu = run_frames(&rom, frames, |_| false);
+
+    let reference_path = path.with_extension("png");
+    let actual_path = path.with_extension("actual.png");
+    let matches = match image::open(&reference_path) {
+        Ok(reference) => {
+            let reference = reference.to_rgba();
+            reference.dimensions() == ppu.screen_buffer.dimensions()
+                && *reference == *ppu.screen_buffer
+        }
+        Err(_) => false,
+    };
+    if !matches {
+        ppu.screen_buffer.save(&actual_path).unwrap();
+    }
+    assert!(
+        matches,
+        "The screen doesn't match {:?}, see {:?}",
+        reference_path, actual_path
+    );
+}
+
 #[test]
 fn cpu_instrs_01() {
     run_test(Path::new(
@@ -81,6 +185,15 @@ fn cpu_instrs_01() {
     ));
 }
 
+// the result is on screen too, with the name of the test
+#[test]
+fn cpu_instrs_01_screen() {
+    run_screenshot_test(
+        Path::new("tests/blargg/cpu_instrs/individual/01-special.gb"),
+        SCREENSHOT_FRAMES,
+    );
+}
+
 #[test]
 fn cpu_instrs_02() {
     run_test(Path::new(
@@ -144,3 +257,204 @@ fn cpu_instrs_11() {
         "tests/blargg/cpu_instrs/individual/11-op a,(hl).gb",
     ));
 }
+
+#[test]
+fn instr_timing() {
+    run_test(Path::new("tests/blargg/instr_timing/instr_timing.gb"));
+}
+
+// the cartridge is CGB-only (0xc0 at 0x143), it measures the dispatch at double speed too.
+// Without a CGB it never reports a result
+#[test]
+#[ignore = "CGB-only ROM, it needs the double speed mode"]
+fn interrupt_time() {
+    run_test(Path::new("tests/blargg/interrupt_time/interrupt_time.gb"));
+}
+
+#[test]
+fn mem_timing_01() {
+    run_test(Path::new(
+        "tests/blargg/mem_timing/individual/01-read_timing.gb",
+    ));
+}
+
+#[test]
+fn mem_timing_2_01() {
+    run_memory_test(Path::new(
+        "tests/blargg/mem_timing-2/rom_singles/01-read_timing.gb",
+    ));
+}
+
+#[test]
+fn mem_timing_02() {
+    run_test(Path::new(
+        "tests/blargg/mem_timing/individual/02-write_timing.gb",
+    ));
+}
+
+#[test]
+fn mem_timing_2_02() {
+    run_memory_test(Path::new(
+        "tests/blargg/mem_timing-2/rom_singles/02-write_timing.gb",
+    ));
+}
+
+#[test]
+fn mem_timing_03() {
+    run_test(Path::new(
+        "tests/blargg/mem_timing/individual/03-modify_timing.gb",
+    ));
+}
+
+#[test]
+fn mem_timing_2_03() {
+    run_memory_test(Path::new(
+        "tests/blargg/mem_timing-2/rom_singles/03-modify_timing.gb",
+    ));
+}
+
+#[test]
+fn oam_bug_1() {
+    run_memory_test(Path::new("tests/blargg/oam_bug/rom_singles/1-lcd_sync.gb"));
+}
+
+#[test]
+fn oam_bug_2() {
+    run_memory_test(Path::new("tests/blargg/oam_bug/rom_singles/2-causes.gb"));
+}
+
+#[test]
+fn oam_bug_3() {
+    run_memory_test(Path::new(
+        "tests/blargg/oam_bug/rom_singles/3-non_causes.gb",
+    ));
+}
+
+#[test]
+fn oam_bug_4() {
+    run_memory_test(Path::new(
+        "tests/blargg/oam_bug/rom_singles/4-scanline_timing.gb",
+    ));
+}
+
+#[test]
+fn oam_bug_5() {
+    run_memory_test(Path::new(
+        "tests/blargg/oam_bug/rom_singles/5-timing_bug.gb",
+    ));
+}
+
+#[test]
+fn oam_bug_6() {
+    run_memory_test(Path::new(
+        "tests/blargg/oam_bug/rom_singles/6-timing_no_bug.gb",
+    ));
+}
+
+#[test]
+fn oam_bug_7() {
+    run_memory_test(Path::new(
+        "tests/blargg/oam_bug/rom_singles/7-timing_effect.gb",
+    ));
+}
+
+#[test]
+fn oam_bug_8() {
+    run_memory_test(Path::new(
+        "tests/blargg/oam_bug/rom_singles/8-instr_effect.gb",
+    ));
+}
+
+#[test]
+#[ignore = "there is no APU yet"]
+fn dmg_sound_01() {
+    run_memory_test(Path::new(
+        "tests/blargg/dmg_sound/rom_singles/01-registers.gb",
+    ));
+}
+
+#[test]
+#[ignore = "there is no APU yet"]
+fn dmg_sound_02() {
+    run_memory_test(Path::new(
+        "tests/blargg/dmg_sound/rom_singles/02-len ctr.gb",
+    ));
+}
+
+#[test]
+#[ignore = "there is no APU yet"]
+fn dmg_sound_03() {
+    run_memory_test(Path::new(
+        "tests/blargg/dmg_sound/rom_singles/03-trigger.gb",
+    ));
+}
+
+#[test]
+#[ignore = "there is no APU yet"]
+fn dmg_sound_04() {
+    run_memory_test(Path::new("tests/blargg/dmg_sound/rom_singles/04-sweep.gb"));
+}
+
+#[test]
+#[ignore = "there is no APU yet"]
+fn dmg_sound_05() {
+    run_memory_test(Path::new(
+        "tests/blargg/dmg_sound/rom_singles/05-sweep details.gb",
+    ));
+}
+
+#[test]
+#[ignore = "there is no APU yet"]
+fn dmg_sound_06() {
+    run_memory_test(Path::new(
+        "tests/blargg/dmg_sound/rom_singles/06-overflow on trigger.gb",
+    ));
+}
+
+#[test]
+#[ignore = "there is no APU yet"]
+fn dmg_sound_07() {
+    run_memory_test(Path::new(
+        "tests/blargg/dmg_sound/rom_singles/07-len sweep period sync.gb",
+    ));
+}
+
+#[test]
+#[ignore = "there is no APU yet"]
+fn dmg_sound_08() {
+    run_memory_test(Path::new(
+        "tests/blargg/dmg_sound/rom_singles/08-len ctr during power.gb",
+    ));
+}
+
+#[test]
+#[ignore = "there is no APU yet"]
+fn dmg_sound_09() {
+    run_memory_test(Path::new(
+        "tests/blargg/dmg_sound/rom_singles/09-wave read while on.gb",
+    ));
+}
+
+#[test]
+#[ignore = "there is no APU yet"]
+fn dmg_sound_10() {
+    run_memory_test(Path::new(
+        "tests/blargg/dmg_sound/rom_singles/10-wave trigger while on.gb",
+    ));
+}
+
+#[test]
+#[ignore = "there is no APU yet"]
+fn dmg_sound_11() {
+    run_memory_test(Path::new(
+        "tests/blargg/dmg_sound/rom_singles/11-regs after power.gb",
+    ));
+}
+
+#[test]
+#[ignore = "there is no APU yet"]
+fn dmg_sound_12() {
+    run_memory_test(Path::new(
+        "tests/blargg/dmg_sound/rom_singles/12-wave write while on.gb",
+    ));
+}
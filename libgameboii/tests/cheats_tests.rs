@@ -0,0 +1,141 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::hand_over_boot_rom;
+use libgameboii::cheats::{CheatCode, Cheats};
+use libgameboii::cpu::CPU;
+use libgameboii::serial::Disconnected;
+use std::io;
+
+const CHEATS: &str = "\
+; patches the byte at 0x4000 to 0x99, only while it's 0x12
+990-00B-AE2 More health
+014210C0 Infinite lives
+";
+
+#[test]
+fn parses_codes() {
+    assert_eq!(
+        CheatCode::parse("00A-17B").unwrap(),
+        CheatCode::GameGenie {
+            addr: 0x4a17,
+            value: 0x00,
+            compare: None,
+        }
+    );
+    assert_eq!(
+        CheatCode::parse("00A-17B-C49").unwrap(),
+        CheatCode::GameGenie {
+            addr: 0x4a17,
+            value: 0x00,
+            compare: Some(0xc8),
+        }
+    );
+    assert_eq!(
+        CheatCode::parse("010238CD").unwrap(),
+        CheatCode::GameShark {
+            bank: 0x01,
+            value: 0x02,
+            addr: 0xcd38,
+        }
+    );
+
+    //not hex, the wrong length, a Game Genie code outside of ROM, a GameShark one inside
+    assert!(CheatCode::parse("00A-17X").is_err());
+    assert!(CheatCode::parse("00A-17").is_err());
+    assert!(CheatCode::parse("00A-172").is_err());
+    assert!(CheatCode::parse("01023800").is_err());
+
+    let cheats = Cheats::parse(io::Cursor::new(CHEATS)).unwrap();
+    assert_eq!(cheats.list().len(), 2);
+    assert_eq!(cheats.list()[0].name, "More health");
+    assert!(cheats.list()[1].enabled);
+
+    let error = Cheats::parse(io::Cursor::new("\n12345 Nope\n"))
+        .err()
+        .unwrap();
+    assert_eq!(error.to_string(), "Invalid cheat code 12345 on line 2");
+}
+
+#[test]
+fn applies_cheats() {
+    let code = [
+        0xfa, 0x00, 0x40, // LD A, (0x4000)
+        0xea, 0x00, 0xc0, // LD (0xc000), A
+        0x18, 0xf8, // JR -8
+    ];
+    let mut cart = vec![0; 0x8000];
+    cart[0x0004..0x0004 + code.len()].copy_from_slice(&code);
+    cart[0x4000] = 0x12;
+
+    let mut cpu = CPU::new(&cart, &hand_over_boot_rom());
+    cpu.cheats = Cheats::parse(io::Cursor::new(CHEATS)).unwrap();
+
+    let mut clock = 0;
+    let mut run = |cpu: &mut CPU| {
+        for _ in 0..200 {
+            cpu.tick(clock, &mut None, &mut Disconnected);
+            clock += 1;
+        }
+    };
+
+    run(&mut cpu);
+    assert_eq!(cpu.RAM[0xc000], 0x99);
+    //the ROM itself stays the same
+    assert_eq!(cpu.RAM[0x4000], 0x12);
+
+    assert_eq!(cpu.cheats.toggle(0), Some(false));
+    run(&mut cpu);
+    assert_eq!(cpu.RAM[0xc000], 0x12);
+
+    //the compare value keeps the code off other banks
+    cpu.cheats.toggle(0);
+    cpu.RAM[0x4000] = 0x34;
+    run(&mut cpu);
+    assert_eq!(cpu.RAM[0xc000], 0x34);
+
+    //GameShark codes are written at vblank
+    assert_eq!(cpu.RAM[0xc010], 0x00);
+    cpu.request_vblank();
+    assert_eq!(cpu.RAM[0xc010], 0x42);
+
+    cpu.cheats.set_all(false);
+    cpu.RAM[0xc010] = 0x00;
+    cpu.request_vblank();
+    assert_eq!(cpu.RAM[0xc010], 0x00);
+}
+
+#[test]
+fn gameshark_ram_banks() {
+    //MBC1+RAM+BATTERY with 4 RAM banks
+    let mut cart = vec![0; 2 * 0x4000];
+    cart[0x147] = 0x03;
+    cart[0x149] = 0x03;
+
+    let mut cpu = CPU::new(&cart, &hand_over_boot_rom());
+    //0xa000 = 0x55 in RAM bank 2, 0xa001 = 0x66 in bank 0
+    cpu.cheats.add("025500A0", "").unwrap();
+    cpu.cheats.add("006601A0", "").unwrap();
+
+    //bank 0 is mapped, bank 2 gets it once it is
+    cpu.request_vblank();
+    assert_eq!(cpu.RAM[0xa000], 0x00);
+    assert_eq!(cpu.RAM[0xa001], 0x66);
+
+    cpu.set_address(0x0000, 0x0a);
+    cpu.set_address(0x6000, 0x01);
+    cpu.set_address(0x4000, 0x02);
+    assert_eq!(cpu.RAM[0xa000], 0x55);
+    assert_eq!(cpu.RAM[0xa001], 0x00);
+
+    //and the other way around
+    cpu.cheats.set_all(false);
+    cpu.set_address(0xa000, 0x00);
+    cpu.cheats.set_all(true);
+    cpu.request_vblank();
+    assert_eq!(cpu.RAM[0xa000], 0x55);
+    assert_eq!(cpu.RAM[0xa001], 0x00);
+    cpu.set_address(0x4000, 0x00);
+    assert_eq!(cpu.RAM[0xa001], 0x66);
+}
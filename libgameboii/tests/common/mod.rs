@@ -0,0 +1,128 @@
+// fixtures shared by the integration tests. Each test file only uses some of them
+#![allow(dead_code)]
+
+use libgameboii::cpu::{CPU, MACHINE_HZ};
+use libgameboii::debugger::DebugHook;
+use libgameboii::serial::Disconnected;
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::io::Write;
+use std::ops::Range;
+use std::rc::Rc;
+
+// the test ROMs run LD B,B when they're done
+pub const LD_B_B: u8 = 0x40;
+
+// the code runs in place of the boot ROM
+pub fn boot_rom(code: &[u8]) -> Vec<u8> {
+    let mut boot = vec![0; 0x100];
+    boot[..code.len()].copy_from_slice(code);
+    boot
+}
+
+// the boot ROM only turns itself off, the cartridge takes over from 0x0004
+pub fn hand_over_boot_rom() -> Vec<u8> {
+    boot_rom(&[
+        0x3e, 0x01, // LD A, 1
+        0xe0, 0x50, // LDH (0x50), A
+    ])
+}
+
+// the test ROMs don't care about the logo, only about the state the DMG boot ROM
+// leaves behind. This one sets that up and turns itself off at 0x00fe
+pub fn dmg_boot_rom() -> Vec<u8> {
+    let mut boot = boot_rom(&[
+        0x31, 0xfe, 0xff, // LD SP, 0xfffe
+        0x3e, 0x91, // LD A, 0x91
+        0xe0, 0x40, // LDH (0x40), A: LCD on
+        0x3e, 0xfc, // LD A, 0xfc
+        0xe0, 0x47, // LDH (0x47), A: BGP
+        0x01, 0xb0, 0x01, // LD BC, 0x01b0
+        0xc5, // PUSH BC
+        0xf1, // POP AF
+        0x01, 0x13, 0x00, // LD BC, 0x0013
+        0x11, 0xd8, 0x00, // LD DE, 0x00d8
+        0x21, 0x4d, 0x01, // LD HL, 0x014d
+    ]);
+    // LDH (0x50), A
+    boot[0xfe] = 0xe0;
+    boot[0xff] = 0x50;
+    boot
+}
+
+// a tiny program that runs in place of the boot ROM and keeps changing A and WRAM
+pub fn counter_program() -> Vec<u8> {
+    boot_rom(&[
+        0x21, 0x00, 0xc0, // LD HL, 0xc000
+        0x34, // INC (HL)
+        0x3c, // INC A
+        0x18, 0xfc, // JR -4
+    ])
+}
+
+pub fn run(cpu: &mut CPU, clocks: Range<u64>) {
+    for clock in clocks {
+        cpu.tick(clock, &mut None, &mut Disconnected);
+    }
+}
+
+pub fn run_until<F: Fn(&CPU) -> bool>(cpu: &mut CPU, clock: &mut u64, done: F) {
+    while !done(cpu) {
+        cpu.tick(*clock, &mut None, &mut Disconnected);
+        *clock += 1;
+    }
+}
+
+// stops the CPU at the first LD B,B
+struct Breakpoint {
+    hit: Rc<Cell<bool>>,
+}
+
+impl DebugHook for Breakpoint {
+    fn before_instruction(&mut self, cpu: &mut CPU) {
+        if cpu.RAM[cpu.PC as usize] == LD_B_B {
+            self.hit.set(true);
+            cpu.should_exit = true;
+        }
+    }
+
+    fn pause(&mut self) {}
+}
+
+// runs the CPU until LD B,B, false if it took longer than timeout_seconds
+pub fn run_until_ld_b_b(cpu: &mut CPU, clock: &mut u64, timeout_seconds: u64) -> bool {
+    let hit = Rc::new(Cell::new(false));
+    cpu.debugger = Some(Box::new(Breakpoint { hit: hit.clone() }));
+
+    while !cpu.should_exit && *clock < timeout_seconds * MACHINE_HZ {
+        cpu.tick(*clock, &mut None, &mut Disconnected);
+        *clock += 1;
+    }
+
+    cpu.debugger = None;
+    hit.get()
+}
+
+// collects what the debugger or the log prints
+#[derive(Clone)]
+pub struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Output {
+    pub fn new() -> Output {
+        Output(Rc::new(RefCell::new(vec![])))
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
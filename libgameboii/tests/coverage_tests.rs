@@ -0,0 +1,79 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::hand_over_boot_rom;
+use libgameboii::coverage;
+use libgameboii::coverage::{BankCoverage, Coverage};
+use libgameboii::cpu::CPU;
+use libgameboii::serial::Disconnected;
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+
+#[test]
+fn maps_code_and_data() {
+    let code = [
+        0xfa, 0x00, 0x40, // LD A, (0x4000)
+        0xcb, 0x37, // SWAP A
+        0x18, 0xfe, // JR -2
+    ];
+    let mut cart = vec![0; 0x8000];
+    cart[0x0004..0x0004 + code.len()].copy_from_slice(&code);
+
+    let mut cpu = CPU::new(&cart, &hand_over_boot_rom());
+    cpu.coverage = Some(RefCell::new(Coverage::new(cart.len())));
+    for clock in 0..200 {
+        cpu.tick(clock, &mut None, &mut Disconnected);
+    }
+
+    let coverage = cpu.coverage.unwrap().into_inner();
+    let flags = coverage.flags();
+    //the boot ROM isn't part of the cartridge
+    assert_eq!(&flags[..0x0004], &[0, 0, 0, 0]);
+    assert_eq!(
+        &flags[0x0004..0x000b],
+        &[
+            coverage::OPCODE,
+            coverage::OPERAND,
+            coverage::OPERAND,
+            coverage::OPCODE,
+            coverage::OPCODE,
+            coverage::OPCODE,
+            coverage::OPERAND,
+        ]
+    );
+    assert_eq!(flags[0x000b], 0);
+    assert_eq!(flags[0x4000], coverage::DATA);
+
+    assert_eq!(coverage.bank_count(), 2);
+    assert_eq!(
+        coverage.bank(0),
+        BankCoverage {
+            size: 0x4000,
+            opcode: 4,
+            operand: 3,
+            data: 0,
+            unused: 0x4000 - 7,
+        }
+    );
+    assert_eq!(coverage.bank(1).data, 1);
+
+    let mut out = vec![];
+    coverage.write_summary(&mut out).unwrap();
+    let summary = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = summary.lines().collect();
+    assert_eq!(lines[1], "  00       4       3       0   16377    0.04%");
+    assert_eq!(lines[3], " all       4       3       1   32760    0.02%");
+
+    //the file is just the flags, and later runs add to it
+    let path = env::temp_dir().join("gameboii_coverage_test.cdl");
+    coverage.save(&path).unwrap();
+    let mut loaded = Coverage::load(&path, cart.len()).unwrap();
+    assert_eq!(loaded.flags(), coverage.flags());
+    loaded.mark(0x0004, coverage::DATA);
+    assert_eq!(loaded.flags()[0x0004], coverage::OPCODE | coverage::DATA);
+
+    assert!(Coverage::load(&path, 0x10000).is_err());
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,82 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::{counter_program, Output};
+use libgameboii::cpu::CPU;
+use libgameboii::debugger::Debugger;
+use libgameboii::serial::Disconnected;
+use std::io;
+use std::io::Write;
+
+#[test]
+fn scripted_debugger_session() {
+    let cart = vec![0; 0x8000];
+    let boot = counter_program();
+
+    let script = "\
+break 0004
+continue
+set a 41
+step
+regs
+write c000 99 98
+x c000 2
+disasm 3
+quit
+";
+    let output = Output::new();
+
+    let mut cpu = CPU::new(&cart, &boot);
+    cpu.debugger = Some(Box::new(Debugger::new(
+        Box::new(io::Cursor::new(script)),
+        Box::new(output.clone()),
+    )));
+
+    let mut current_clock = 0;
+    while !cpu.should_exit {
+        cpu.tick(current_clock, &mut None, &mut Disconnected);
+        current_clock += 1;
+    }
+
+    let output = output.text();
+    println!("{}", output);
+
+    assert!(output.contains("00:0004"));
+    assert!(output.contains("AF 42"));
+    assert!(output.contains("PC 0005"));
+    assert!(output.contains("c000: 99 98"));
+    assert!(output.contains("=> 0005  JR"));
+    assert_eq!(cpu.PC, 5);
+}
+
+// a terminal that went away
+struct Closed;
+
+impl Write for Closed {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn keeps_running_without_a_terminal() {
+    let cart = vec![0; 0x8000];
+    let boot = counter_program();
+
+    let mut cpu = CPU::new(&cart, &boot);
+    cpu.debugger = Some(Box::new(Debugger::new(
+        Box::new(io::Cursor::new("step\n")),
+        Box::new(Closed),
+    )));
+
+    for current_clock in 0..1000 {
+        cpu.tick(current_clock, &mut None, &mut Disconnected);
+    }
+    assert!(!cpu.should_exit);
+    assert!(cpu.RAM[0xc000] > 0);
+}
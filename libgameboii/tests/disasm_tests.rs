@@ -0,0 +1,67 @@
+extern crate libgameboii;
+
+use libgameboii::disasm;
+
+#[test]
+fn disassembles_to_rgbds() {
+    let code = [
+        0x3e, 0x12, // ld a, $12
+        0x18, 0xfe, // jr $4002
+        0xe0, 0x44, // ldh [$ff44], a
+        0xf2, // ldh a, [c]
+        0xcb, 0x7c, // bit 7, h
+        0xf8, 0xfd, // ld hl, sp-3
+        0xe8, 0x05, // add sp, 5
+        0xff, // rst $38
+        0x2a, // ld a, [hl+]
+        0xea, 0x00, 0xc0, // ld [$c000], a
+        0xe9, // jp hl
+        0xd3, // not an instruction
+        0x10, 0x00, // stop
+        0x01, 0xcb, // cut short by the end of the data
+    ];
+    let mut rom = vec![0; 0x8000];
+    rom[0x4000..0x4000 + code.len()].copy_from_slice(&code);
+
+    let instructions = disasm::disassemble(&rom[0x4000..0x4000 + code.len()], 1, 0x4000);
+    let text: Vec<String> = instructions.iter().map(|i| i.rgbds()).collect();
+    assert_eq!(
+        text,
+        vec![
+            "ld a, $12",
+            "jr $4002",
+            "ldh [$ff44], a",
+            "ldh a, [c]",
+            "bit 7, h",
+            "ld hl, sp-3",
+            "add sp, 5",
+            "rst $38",
+            "ld a, [hl+]",
+            "ld [$c000], a",
+            "jp hl",
+            "db $d3",
+            "stop",
+            "db $01",
+            "db $cb",
+        ]
+    );
+    assert_eq!(instructions[1].text(), "JR 0x4002");
+    assert_eq!(instructions[4].addr, 0x4007);
+
+    // the same bytes, found through the bank
+    let bank = disasm::disassemble_bank(&rom, 1, 0x4000).unwrap();
+    assert_eq!(bank[1].rgbds(), "jr $4002");
+    assert_eq!(bank[1].bank, 1);
+
+    let mut out = vec![];
+    disasm::write_rgbds(&mut out, &bank[..2], None).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "SECTION \"ROM Bank $01\", ROMX[$4000], BANK[$01]\n\n\
+         \tld a, $12               ; $4000\n\
+         \tjr $4002                ; $4002\n"
+    );
+
+    assert!(disasm::disassemble_bank(&rom, 1, 0x0100).is_err());
+    assert!(disasm::disassemble_bank(&rom, 2, 0x4000).is_err());
+}
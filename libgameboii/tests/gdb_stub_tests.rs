@@ -0,0 +1,115 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::counter_program;
+use libgameboii::cpu::CPU;
+use libgameboii::gdb_stub::GdbStub;
+use libgameboii::serial::Disconnected;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+// the gdb side, just enough of it to script a session
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0; 1];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn send(&mut self, data: &str) {
+        let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", data, sum).unwrap();
+        assert_eq!(self.read_byte(), b'+');
+    }
+
+    fn receive(&mut self) -> String {
+        while self.read_byte() != b'$' {}
+        let mut data = vec![];
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+        self.read_byte();
+        self.read_byte();
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    fn command(&mut self, data: &str) -> String {
+        self.send(data);
+        self.receive()
+    }
+}
+
+#[test]
+fn scripted_gdb_session() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = thread::spawn(move || {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let mut gdb = Client { stream: stream };
+
+        assert_eq!(gdb.command("qSupported:swbreak+"), "PacketSize=1000");
+        assert_eq!(gdb.command("?"), "S05");
+        assert_eq!(gdb.command("g").len(), 24);
+        assert!(gdb.command("g").ends_with("0000"));
+
+        // run to a breakpoint
+        assert_eq!(gdb.command("Z0,4,1"), "OK");
+        assert_eq!(gdb.command("c"), "S05");
+        assert_eq!(gdb.command("p5"), "0400");
+        assert_eq!(gdb.command("p3"), "00c0");
+
+        // registers and memory
+        assert_eq!(gdb.command("P1=3412"), "OK");
+        assert_eq!(gdb.command("p1"), "3412");
+        assert_eq!(gdb.command("p9"), "E01");
+        assert_eq!(gdb.command("Mc000,2:abcd"), "OK");
+        assert_eq!(gdb.command("mc000,2"), "abcd");
+
+        assert_eq!(gdb.command("s"), "S05");
+        assert_eq!(gdb.command("p5"), "0500");
+        assert_eq!(gdb.command("z0,4,1"), "OK");
+
+        // INC (HL) writes c000, stop right after it
+        assert_eq!(gdb.command("Z2,c000,1"), "OK");
+        assert_eq!(gdb.command("c"), "T05watch:c000;");
+        assert_eq!(gdb.command("p5"), "0400");
+        assert_eq!(gdb.command("mc000,1"), "ac");
+        assert_eq!(gdb.command("z2,c000,1"), "OK");
+
+        // nothing left to stop it but a break
+        gdb.send("c");
+        gdb.stream.write_all(&[0x03]).unwrap();
+        assert_eq!(gdb.receive(), "S02");
+
+        gdb.send("k");
+    });
+
+    let cart = vec![0; 0x8000];
+    let boot = counter_program();
+    let mut cpu = CPU::new(&cart, &boot);
+    cpu.debugger = Some(Box::new(GdbStub::accept(&listener).unwrap()));
+
+    // if the client gives up, the stub detaches and the game would run forever
+    let mut clock = 0;
+    while !cpu.should_exit && clock < 100 * 70224 {
+        cpu.tick(clock, &mut None, &mut Disconnected);
+        clock += 1;
+    }
+
+    client.join().unwrap();
+}
@@ -0,0 +1,124 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::{hand_over_boot_rom, run};
+use libgameboii::cpu::CPU;
+
+// the vblank handler sets C to 0x2a and waits there
+const VBLANK_HANDLER: [u8; 4] = [
+    0x0e, 0x2a, // LD C, 0x2a
+    0x18, 0xfe, // JR -2
+];
+
+fn boot_rom(code: &[u8]) -> Vec<u8> {
+    let mut boot = common::boot_rom(code);
+    boot[0x40..0x44].copy_from_slice(&VBLANK_HANDLER);
+    boot
+}
+
+#[test]
+fn dispatches_after_the_instruction_following_ei() {
+    let code = [
+        0x31, 0xfe, 0xff, // LD SP, 0xfffe
+        0x3e, 0x01, // LD A, 1
+        0xe0, 0xff, // LDH (0xff), A: IE
+        0xe0, 0x0f, // LDH (0x0f), A: IF
+        0xfb, // EI
+        0x04, // INC B: still runs
+        0x04, // INC B
+    ];
+    let cart = vec![0; 0x8000];
+    let mut cpu = CPU::new(&cart, &boot_rom(&code));
+
+    // the dispatch starts at clock 52, after INC B, and takes 5 cycles
+    run(&mut cpu, 0..53);
+    assert_eq!(cpu.PC, 0x0040);
+    assert_eq!(cpu.next_clock(), 72);
+    assert!(!cpu.interrupts_enabled());
+    assert_eq!(cpu.RAM[0xff0f], 0x00);
+    assert_eq!(cpu.SP, 0xfffc);
+    assert_eq!(&cpu.RAM[0xfffc..0xfffe], &[0x0b, 0x00]);
+
+    run(&mut cpu, 53..200);
+    unsafe {
+        assert_eq!(cpu.BC.r8.first, 1);
+        assert_eq!(cpu.BC.r8.second, 0x2a);
+    }
+}
+
+#[test]
+fn di_is_immediate_and_reti_is_not_delayed() {
+    let code = [
+        0x31, 0xfe, 0xff, // LD SP, 0xfffe
+        0x3e, 0x01, // LD A, 1
+        0xe0, 0xff, // LDH (0xff), A: IE
+        0xe0, 0x0f, // LDH (0x0f), A: IF
+        0xfb, // EI
+        0xf3, // DI: the EI never takes effect
+        0x00, // NOP
+        0x04, // INC B
+        0x21, 0x14, 0x00, // LD HL, 0x0014
+        0xe5, // PUSH HL
+        0xd9, // RETI: the interrupt comes before the INC B at 0x0014
+        0x00, 0x00, // padding
+        0x04, // INC B
+    ];
+    let cart = vec![0; 0x8000];
+    let mut cpu = CPU::new(&cart, &boot_rom(&code));
+
+    run(&mut cpu, 0..400);
+    unsafe {
+        assert_eq!(cpu.BC.r8.first, 1);
+        assert_eq!(cpu.BC.r8.second, 0x2a);
+    }
+    assert_eq!(&cpu.RAM[0xfffc..0xfffe], &[0x14, 0x00]);
+}
+
+// with SP at 0, the high byte of PC is pushed into IE. The dispatch is cancelled
+// when that turns the interrupt off, and jumps to 0 instead of the handler
+fn push_into_ie_cart(code_addr: usize) -> Vec<u8> {
+    let code = [
+        0x3e, 0x01, // LD A, 1
+        0xe0, 0xff, // LDH (0xff), A: IE
+        0xe0, 0x0f, // LDH (0x0f), A: IF
+        0x31, 0x00, 0x00, // LD SP, 0
+        0xfb, // EI
+        0x00, // NOP
+    ];
+
+    let mut cart = vec![0; 0x8000];
+    // what runs after a cancelled dispatch
+    cart[0x0000..0x0004].copy_from_slice(&[
+        0x0e, 0x01, // LD C, 1
+        0x18, 0xfe, // JR -2
+    ]);
+    cart[0x0004..0x0007].copy_from_slice(&[0xc3, code_addr as u8, (code_addr >> 8) as u8]);
+    cart[0x0040..0x0044].copy_from_slice(&VBLANK_HANDLER);
+    cart[code_addr..code_addr + code.len()].copy_from_slice(&code);
+    cart
+}
+
+#[test]
+fn pushing_into_ie_cancels_the_dispatch() {
+    // 0x02 turns vblank off
+    let cart = push_into_ie_cart(0x0200);
+    let mut cpu = CPU::new(&cart, &hand_over_boot_rom());
+    run(&mut cpu, 0..400);
+    unsafe {
+        assert_eq!(cpu.BC.r8.second, 1);
+    }
+    assert_eq!(cpu.RAM[0xffff], 0x02);
+    assert_eq!(cpu.RAM[0xfffe], 0x0b);
+    // nothing was acknowledged
+    assert_eq!(cpu.RAM[0xff0f], 0x01);
+
+    // 0x01 leaves it on
+    let cart = push_into_ie_cart(0x0100);
+    let mut cpu = CPU::new(&cart, &hand_over_boot_rom());
+    run(&mut cpu, 0..400);
+    unsafe {
+        assert_eq!(cpu.BC.r8.second, 0x2a);
+    }
+    assert_eq!(cpu.RAM[0xff0f], 0x00);
+}
@@ -0,0 +1,116 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::boot_rom;
+use libgameboii::cpu::CPU;
+use libgameboii::link_cable;
+use libgameboii::net_link::NetLink;
+use libgameboii::serial::SerialDevice;
+use std::net::TcpListener;
+use std::thread;
+
+const SB_REGISTER: usize = 0xff01;
+const CLOCKS: u64 = 20000;
+//two frames, so the sides sync up a couple of times
+const NET_CLOCKS: u64 = 2 * 70224;
+
+// a tiny program that runs in place of the boot ROM:
+// it loads SB, starts a transfer with the given SC value and spins until it's over
+fn transfer_program(sb: u8, sc: u8) -> Vec<u8> {
+    let code = [
+        0x3e, sb, // LD A, sb
+        0xe0, 0x01, // LDH (SB), A
+        0x3e, sc, // LD A, sc
+        0xe0, 0x02, // LDH (SC), A
+        0xf0, 0x02, // LDH A, (SC)
+        0xe6, 0x80, // AND 0x80
+        0x20, 0xfa, // JR NZ, -6
+        0x18, 0xfe, // JR -2
+    ];
+
+    boot_rom(&code)
+}
+
+#[test]
+fn link_cable_exchanges_bytes() {
+    let cart = vec![0; 0x8000];
+    let master_boot = transfer_program(0x42, 0x81);
+    let slave_boot = transfer_program(0x17, 0x80);
+
+    let mut master = CPU::new(&cart, &master_boot);
+    let mut slave = CPU::new(&cart, &slave_boot);
+
+    let (mut master_port, mut slave_port) = link_cable::connect();
+
+    for current_clock in 0..CLOCKS {
+        master.tick(current_clock, &mut None, &mut master_port);
+        slave.tick(current_clock, &mut None, &mut slave_port);
+    }
+
+    assert_eq!(master.RAM[SB_REGISTER], 0x17);
+    assert_eq!(slave.RAM[SB_REGISTER], 0x42);
+}
+
+// remembers every byte the gameboy sends
+struct Capture {
+    sent: Vec<u8>,
+}
+
+impl SerialDevice for Capture {
+    fn exchange_byte(&mut self, out: u8) -> u8 {
+        self.sent.push(out);
+        0xff
+    }
+}
+
+#[test]
+fn unconnected_transfer_reads_ff() {
+    let cart = vec![0; 0x8000];
+    let boot = transfer_program(0x42, 0x81);
+
+    let mut cpu = CPU::new(&cart, &boot);
+    let mut capture = Capture { sent: vec![] };
+
+    for current_clock in 0..CLOCKS {
+        cpu.tick(current_clock, &mut None, &mut capture);
+    }
+
+    assert_eq!(cpu.RAM[SB_REGISTER], 0xff);
+    assert_eq!(capture.sent, vec![0x42]);
+}
+
+fn run_net_linked(mut link: NetLink, sb: u8, sc: u8) -> u8 {
+    let cart = vec![0; 0x8000];
+    let boot = transfer_program(sb, sc);
+
+    let mut cpu = CPU::new(&cart, &boot);
+
+    //stop right on a sync point, so neither side hangs up on the other
+    for current_clock in 0..NET_CLOCKS + 1 {
+        cpu.tick(current_clock, &mut None, &mut link);
+        if let Some(error) = link.take_error() {
+            panic!("{}", error);
+        }
+    }
+
+    cpu.RAM[SB_REGISTER]
+}
+
+#[test]
+fn net_link_exchanges_bytes() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let master = thread::spawn(move || {
+        let link = NetLink::connect(addr).unwrap();
+        run_net_linked(link, 0x42, 0x81)
+    });
+
+    let link = NetLink::accept(&listener).unwrap();
+    let slave_sb = run_net_linked(link, 0x17, 0x80);
+    let master_sb = master.join().unwrap();
+
+    assert_eq!(master_sb, 0x17);
+    assert_eq!(slave_sb, 0x42);
+}
@@ -0,0 +1,93 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::{boot_rom, run};
+use libgameboii::cpu::{LockUp, CPU};
+use libgameboii::ppu::FRAME_CLOCKS;
+use libgameboii::save_state::SaveState;
+use libgameboii::serial::Disconnected;
+
+const ILLEGAL_OPCODES: [u8; 11] = [
+    0xd3, 0xdb, 0xdd, 0xe3, 0xe4, 0xeb, 0xec, 0xed, 0xf4, 0xfc, 0xfd,
+];
+
+fn lock_up_program(opcode: u8) -> Vec<u8> {
+    let code = [
+        0x3e, 0x91, // LD A, 0x91
+        0xe0, 0x40, // LDH (0x40), A: LCD on
+        0x3e, 0x01, // LD A, 1
+        0xe0, 0xff, // LDH (0xff), A: IE
+        0xfb, // EI: vblank can't get it out either
+        opcode, 0x04, // INC B: never runs
+    ];
+
+    boot_rom(&code)
+}
+
+#[test]
+fn illegal_opcodes_lock_up_the_cpu() {
+    let cart = vec![0; 0x8000];
+    for &opcode in &ILLEGAL_OPCODES {
+        let boot = lock_up_program(opcode);
+        let mut cpu = CPU::new(&cart, &boot);
+
+        run(&mut cpu, 0..100);
+        let lock_up = LockUp {
+            pc: 0x0009,
+            opcode: opcode,
+        };
+        assert_eq!(cpu.locked_up(), Some(lock_up), "{:02x}", opcode);
+        // reported only once
+        assert_eq!(cpu.take_lock_up(), Some(lock_up));
+        assert_eq!(cpu.take_lock_up(), None);
+
+        let pc = cpu.PC;
+        run(&mut cpu, 100..FRAME_CLOCKS * 2);
+        assert_eq!(cpu.PC, pc);
+        unsafe {
+            assert_eq!(cpu.BC.r8.first, 0);
+        }
+        assert_eq!(cpu.take_lock_up(), None);
+    }
+}
+
+#[test]
+fn the_rest_keeps_running() {
+    let cart = vec![0; 0x8000];
+    let boot = lock_up_program(0xdd);
+    let mut cpu = CPU::new(&cart, &boot);
+
+    run(&mut cpu, 0..100);
+    assert!(cpu.locked_up().is_some());
+    let div = cpu.RAM[0xff04];
+    let sp = cpu.SP;
+
+    // the PPU gets through a whole frame, the vblank interrupt is requested and never served
+    let mut lines = vec![];
+    for clock in 100..FRAME_CLOCKS + 100 {
+        cpu.tick(clock, &mut None, &mut Disconnected);
+        lines.push(cpu.RAM[0xff44]);
+    }
+    assert_eq!(lines.iter().max(), Some(&153));
+    // DIV wraps around once in a frame
+    let ticks = cpu.RAM[0xff04].wrapping_sub(div) as u64 + 256;
+    assert!(ticks == FRAME_CLOCKS / 256 || ticks == FRAME_CLOCKS / 256 + 1);
+    assert_eq!(cpu.RAM[0xff0f] & 0x01, 0x01);
+    assert_eq!(cpu.SP, sp);
+}
+
+#[test]
+fn save_states_keep_the_lock_up() {
+    let cart = vec![0; 0x8000];
+    let boot = lock_up_program(0xdd);
+    let mut cpu = CPU::new(&cart, &boot);
+    run(&mut cpu, 0..100);
+
+    let state = SaveState::capture(&cpu, 100);
+    let mut restored = CPU::new(&cart, &boot);
+    state.restore(&mut restored).unwrap();
+    assert_eq!(restored.locked_up(), cpu.locked_up());
+    // the event already went to whoever ran it before
+    assert_eq!(restored.take_lock_up(), None);
+}
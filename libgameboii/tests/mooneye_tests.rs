@@ -0,0 +1,197 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::{dmg_boot_rom, run_until_ld_b_b};
+use libgameboii::cpu::CPU;
+use std::panic;
+use std::path::Path;
+
+// the mooneye-gb test ROMs run LD B,B when they're done. The registers
+// hold the Fibonacci numbers if the test passed, and 0x42 if it failed
+const PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+// the tests finish in a handful of frames, the ones that hang are given up on
+const TIMEOUT_SECONDS: u64 = 10;
+
+// ROMs that don't pass yet. They still run, and a test fails once one of them
+// starts passing, so it can come off the list
+const KNOWN_FAILURES: &[&str] = &[
+    "tests/gekkio/acceptance/add_sp_e_timing.gb",
+    "tests/gekkio/acceptance/bits/unused_hwio-GS.gb",
+    "tests/gekkio/acceptance/boot_hwio-dmgABCmgb.gb",
+    "tests/gekkio/acceptance/call_cc_timing.gb",
+    "tests/gekkio/acceptance/call_cc_timing2.gb",
+    "tests/gekkio/acceptance/call_timing.gb",
+    "tests/gekkio/acceptance/call_timing2.gb",
+    "tests/gekkio/acceptance/di_timing-GS.gb",
+    "tests/gekkio/acceptance/halt_ime0_ei.gb",
+    "tests/gekkio/acceptance/halt_ime0_nointr_timing.gb",
+    "tests/gekkio/acceptance/halt_ime1_timing.gb",
+    "tests/gekkio/acceptance/halt_ime1_timing2-GS.gb",
+    "tests/gekkio/acceptance/if_ie_registers.gb",
+    "tests/gekkio/acceptance/jp_cc_timing.gb",
+    "tests/gekkio/acceptance/jp_timing.gb",
+    "tests/gekkio/acceptance/ld_hl_sp_e_timing.gb",
+    "tests/gekkio/acceptance/oam_dma/sources-dmgABCmgbS.gb",
+    "tests/gekkio/acceptance/oam_dma_restart.gb",
+    "tests/gekkio/acceptance/oam_dma_start.gb",
+    "tests/gekkio/acceptance/oam_dma_timing.gb",
+    "tests/gekkio/acceptance/ppu/hblank_ly_scx_timing-GS.gb",
+    "tests/gekkio/acceptance/ppu/intr_1_2_timing-GS.gb",
+    "tests/gekkio/acceptance/ppu/intr_2_0_timing.gb",
+    "tests/gekkio/acceptance/ppu/intr_2_mode0_timing.gb",
+    "tests/gekkio/acceptance/ppu/intr_2_mode0_timing_sprites.gb",
+    "tests/gekkio/acceptance/ppu/intr_2_mode3_timing.gb",
+    "tests/gekkio/acceptance/ppu/intr_2_oam_ok_timing.gb",
+    "tests/gekkio/acceptance/ppu/lcdon_timing-dmgABCmgbS.gb",
+    "tests/gekkio/acceptance/ppu/lcdon_write_timing-GS.gb",
+    "tests/gekkio/acceptance/ppu/stat_irq_blocking.gb",
+    "tests/gekkio/acceptance/ppu/stat_lyc_onoff.gb",
+    "tests/gekkio/acceptance/ppu/vblank_stat_intr-GS.gb",
+    "tests/gekkio/acceptance/push_timing.gb",
+    "tests/gekkio/acceptance/ret_cc_timing.gb",
+    "tests/gekkio/acceptance/ret_timing.gb",
+    "tests/gekkio/acceptance/reti_timing.gb",
+    "tests/gekkio/acceptance/rst_timing.gb",
+    "tests/gekkio/acceptance/serial/boot_sclk_align-dmgABCmgb.gb",
+    "tests/gekkio/acceptance/timer/div_write.gb",
+    "tests/gekkio/acceptance/timer/rapid_toggle.gb",
+    "tests/gekkio/acceptance/timer/tim00.gb",
+    "tests/gekkio/acceptance/timer/tim01_div_trigger.gb",
+    "tests/gekkio/acceptance/timer/tim10.gb",
+    "tests/gekkio/acceptance/timer/tim10_div_trigger.gb",
+    "tests/gekkio/acceptance/timer/tim11.gb",
+    "tests/gekkio/acceptance/timer/tima_reload.gb",
+    "tests/gekkio/acceptance/timer/tima_write_reloading.gb",
+    "tests/gekkio/acceptance/timer/tma_write_reloading.gb",
+    "tests/gekkio/emulator-only/mbc1/multicart_rom_8Mb.gb",
+];
+
+fn run_rom(path: &str) -> Result<(), String> {
+    let rom = libgameboii::open_rom(&Path::new(path)).map_err(|error| error.to_string())?;
+    let boot = dmg_boot_rom();
+
+    let mut cpu = CPU::new(&rom, &boot);
+    let mut clock = 0;
+    if !run_until_ld_b_b(&mut cpu, &mut clock, TIMEOUT_SECONDS) {
+        return Err(String::from("timed out before LD B,B"));
+    }
+
+    let registers = unsafe {
+        [
+            cpu.BC.r8.first,
+            cpu.BC.r8.second,
+            cpu.DE.r8.first,
+            cpu.DE.r8.second,
+            cpu.HL.r8.first,
+            cpu.HL.r8.second,
+        ]
+    };
+    if registers == PASSED {
+        Ok(())
+    } else {
+        Err(format!("failed with registers {:?}", registers))
+    }
+}
+
+fn check(path: &str) {
+    // unimplemented hardware panics, which counts as a failure
+    let result = panic::catch_unwind(|| run_rom(path))
+        .unwrap_or_else(|_| Err(String::from("the emulator panicked")));
+
+    match (result, KNOWN_FAILURES.contains(&path)) {
+        (Ok(()), false) | (Err(_), true) => {}
+        (Ok(()), true) => panic!("{} passes now, take it off KNOWN_FAILURES", path),
+        (Err(error), false) => panic!("{} {}", path, error),
+    }
+}
+
+macro_rules! mooneye_tests {
+    ($($name:ident: $path:expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                check(concat!("tests/gekkio/", $path));
+            }
+        )*
+    };
+}
+
+// the DMG tests. The ones for the other models and the manual ones are left out
+mooneye_tests! {
+    add_sp_e_timing: "acceptance/add_sp_e_timing.gb",
+    bits_mem_oam: "acceptance/bits/mem_oam.gb",
+    bits_reg_f: "acceptance/bits/reg_f.gb",
+    bits_unused_hwio_gs: "acceptance/bits/unused_hwio-GS.gb",
+    boot_hwio_dmg_abc_mgb: "acceptance/boot_hwio-dmgABCmgb.gb",
+    boot_regs_dmg_abc: "acceptance/boot_regs-dmgABC.gb",
+    call_cc_timing: "acceptance/call_cc_timing.gb",
+    call_cc_timing2: "acceptance/call_cc_timing2.gb",
+    call_timing: "acceptance/call_timing.gb",
+    call_timing2: "acceptance/call_timing2.gb",
+    di_timing_gs: "acceptance/di_timing-GS.gb",
+    div_timing: "acceptance/div_timing.gb",
+    ei_sequence: "acceptance/ei_sequence.gb",
+    ei_timing: "acceptance/ei_timing.gb",
+    halt_ime0_ei: "acceptance/halt_ime0_ei.gb",
+    halt_ime0_nointr_timing: "acceptance/halt_ime0_nointr_timing.gb",
+    halt_ime1_timing: "acceptance/halt_ime1_timing.gb",
+    halt_ime1_timing2_gs: "acceptance/halt_ime1_timing2-GS.gb",
+    if_ie_registers: "acceptance/if_ie_registers.gb",
+    interrupts_ie_push: "acceptance/interrupts/ie_push.gb",
+    intr_timing: "acceptance/intr_timing.gb",
+    jp_cc_timing: "acceptance/jp_cc_timing.gb",
+    jp_timing: "acceptance/jp_timing.gb",
+    ld_hl_sp_e_timing: "acceptance/ld_hl_sp_e_timing.gb",
+    oam_dma_basic: "acceptance/oam_dma/basic.gb",
+    oam_dma_reg_read: "acceptance/oam_dma/reg_read.gb",
+    oam_dma_sources_dmg_abc_mgb_s: "acceptance/oam_dma/sources-dmgABCmgbS.gb",
+    oam_dma_restart: "acceptance/oam_dma_restart.gb",
+    oam_dma_start: "acceptance/oam_dma_start.gb",
+    oam_dma_timing: "acceptance/oam_dma_timing.gb",
+    pop_timing: "acceptance/pop_timing.gb",
+    ppu_hblank_ly_scx_timing_gs: "acceptance/ppu/hblank_ly_scx_timing-GS.gb",
+    ppu_intr_1_2_timing_gs: "acceptance/ppu/intr_1_2_timing-GS.gb",
+    ppu_intr_2_0_timing: "acceptance/ppu/intr_2_0_timing.gb",
+    ppu_intr_2_mode0_timing: "acceptance/ppu/intr_2_mode0_timing.gb",
+    ppu_intr_2_mode0_timing_sprites: "acceptance/ppu/intr_2_mode0_timing_sprites.gb",
+    ppu_intr_2_mode3_timing: "acceptance/ppu/intr_2_mode3_timing.gb",
+    ppu_intr_2_oam_ok_timing: "acceptance/ppu/intr_2_oam_ok_timing.gb",
+    ppu_lcdon_timing_dmg_abc_mgb_s: "acceptance/ppu/lcdon_timing-dmgABCmgbS.gb",
+    ppu_lcdon_write_timing_gs: "acceptance/ppu/lcdon_write_timing-GS.gb",
+    ppu_stat_irq_blocking: "acceptance/ppu/stat_irq_blocking.gb",
+    ppu_stat_lyc_onoff: "acceptance/ppu/stat_lyc_onoff.gb",
+    ppu_vblank_stat_intr_gs: "acceptance/ppu/vblank_stat_intr-GS.gb",
+    push_timing: "acceptance/push_timing.gb",
+    rapid_di_ei: "acceptance/rapid_di_ei.gb",
+    ret_cc_timing: "acceptance/ret_cc_timing.gb",
+    ret_timing: "acceptance/ret_timing.gb",
+    reti_intr_timing: "acceptance/reti_intr_timing.gb",
+    reti_timing: "acceptance/reti_timing.gb",
+    rst_timing: "acceptance/rst_timing.gb",
+    serial_boot_sclk_align_dmg_abc_mgb: "acceptance/serial/boot_sclk_align-dmgABCmgb.gb",
+    timer_div_write: "acceptance/timer/div_write.gb",
+    timer_rapid_toggle: "acceptance/timer/rapid_toggle.gb",
+    timer_tim00: "acceptance/timer/tim00.gb",
+    timer_tim00_div_trigger: "acceptance/timer/tim00_div_trigger.gb",
+    timer_tim01: "acceptance/timer/tim01.gb",
+    timer_tim01_div_trigger: "acceptance/timer/tim01_div_trigger.gb",
+    timer_tim10: "acceptance/timer/tim10.gb",
+    timer_tim10_div_trigger: "acceptance/timer/tim10_div_trigger.gb",
+    timer_tim11: "acceptance/timer/tim11.gb",
+    timer_tim11_div_trigger: "acceptance/timer/tim11_div_trigger.gb",
+    timer_tima_reload: "acceptance/timer/tima_reload.gb",
+    timer_tima_write_reloading: "acceptance/timer/tima_write_reloading.gb",
+    timer_tma_write_reloading: "acceptance/timer/tma_write_reloading.gb",
+    mbc1_bits_ram_en: "emulator-only/mbc1/bits_ram_en.gb",
+    mbc1_multicart_rom_8mb: "emulator-only/mbc1/multicart_rom_8Mb.gb",
+    mbc1_ram_256kb: "emulator-only/mbc1/ram_256Kb.gb",
+    mbc1_ram_64kb: "emulator-only/mbc1/ram_64Kb.gb",
+    mbc1_rom_16mb: "emulator-only/mbc1/rom_16Mb.gb",
+    mbc1_rom_1mb: "emulator-only/mbc1/rom_1Mb.gb",
+    mbc1_rom_2mb: "emulator-only/mbc1/rom_2Mb.gb",
+    mbc1_rom_4mb: "emulator-only/mbc1/rom_4Mb.gb",
+    mbc1_rom_512kb: "emulator-only/mbc1/rom_512Kb.gb",
+    mbc1_rom_8mb: "emulator-only/mbc1/rom_8Mb.gb",
+}
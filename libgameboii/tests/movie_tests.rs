@@ -0,0 +1,76 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::boot_rom;
+use libgameboii::cpu::CPU;
+use libgameboii::joypad;
+use libgameboii::movie::Movie;
+use libgameboii::ppu::FRAME_CLOCKS;
+use libgameboii::serial::Disconnected;
+
+const FRAMES: u64 = 6;
+
+// a tiny program that runs in place of the boot ROM:
+// it selects the directions and keeps logging P1 to WRAM
+fn joypad_logger_program() -> Vec<u8> {
+    let code = [
+        0x3e, 0x20, // LD A, 0x20
+        0xe0, 0x00, // LDH (P1), A
+        0x21, 0x00, 0xc0, // LD HL, 0xc000
+        0xf0, 0x00, // LDH A, (P1)
+        0x77, // LD (HL), A
+        0x2c, // INC L
+        0x18, 0xfa, // JR -6
+    ];
+
+    boot_rom(&code)
+}
+
+fn held_buttons(frame: u64) -> u8 {
+    match frame % 3 {
+        0 => 0,
+        1 => joypad::RIGHT,
+        _ => joypad::UP | joypad::A,
+    }
+}
+
+#[test]
+fn played_movie_matches_the_recording() {
+    let cart = vec![0; 0x8000];
+    let boot = joypad_logger_program();
+
+    let mut cpu = CPU::new(&cart, &boot);
+    let mut movie = Movie::new(&cpu, None);
+
+    let mut saw_right = false;
+    for current_clock in 0..FRAME_CLOCKS * FRAMES {
+        movie.record(
+            &mut cpu,
+            current_clock,
+            held_buttons(current_clock / FRAME_CLOCKS),
+        );
+        cpu.tick(current_clock, &mut None, &mut Disconnected);
+
+        saw_right |= cpu.RAM[0xc000..0xc100].contains(&0xee);
+    }
+    assert!(saw_right);
+    assert_eq!(movie.len(), FRAMES as usize);
+
+    let mut file = vec![];
+    movie.write_to(&mut file).unwrap();
+    let movie = Movie::read_from(&mut &file[..]).unwrap();
+
+    let mut replay_cpu = CPU::new(&cart, &boot);
+    movie.check_rom(&replay_cpu).unwrap();
+
+    let mut current_clock = 0;
+    while movie.play(&mut replay_cpu, current_clock) {
+        replay_cpu.tick(current_clock, &mut None, &mut Disconnected);
+        current_clock += 1;
+    }
+
+    assert_eq!(current_clock, FRAME_CLOCKS * FRAMES);
+    assert_eq!(replay_cpu.PC, cpu.PC);
+    assert!(replay_cpu.RAM[..] == cpu.RAM[..]);
+}
@@ -0,0 +1,179 @@
+extern crate image;
+extern crate libgameboii;
+
+use libgameboii::printer::Printer;
+use libgameboii::serial::SerialDevice;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0f;
+
+const DEVICE_ID: u8 = 0x81;
+
+// one row of 20 tiles
+const ROW_SIZE_BYTES: usize = 20 * 16;
+
+fn packet(command: u8, compressed: bool, data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![
+        0x88,
+        0x33,
+        command,
+        compressed as u8,
+        data.len() as u8,
+        (data.len() >> 8) as u8,
+    ];
+    bytes.extend_from_slice(data);
+
+    let checksum = bytes[2..]
+        .iter()
+        .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+    bytes.push(checksum as u8);
+    bytes.push((checksum >> 8) as u8);
+    // the device ID and the status are shifted in on these
+    bytes.extend_from_slice(&[0, 0]);
+    bytes
+}
+
+// sends a packet and returns the device ID and status it answered with
+fn send(printer: &mut Printer, bytes: &[u8]) -> (u8, u8) {
+    let replies: Vec<u8> = bytes
+        .iter()
+        .map(|&byte| printer.exchange_byte(byte))
+        .collect();
+    (replies[replies.len() - 2], replies[replies.len() - 1])
+}
+
+fn output_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("gameboii_printer_{}", name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn print(printer: &mut Printer, margins: u8, palette: u8) -> u8 {
+    let (_, status) = send(printer, &packet(PRINT, false, &[1, margins, palette, 0x40]));
+    assert!(printer.take_error().is_none());
+    status
+}
+
+fn printed_page(dir: &PathBuf, page: usize) -> image::RgbaImage {
+    image::open(dir.join(format!("print_{:04}.png", page)))
+        .unwrap()
+        .to_rgba()
+}
+
+fn gray(img: &image::RgbaImage, x: u32, y: u32) -> u8 {
+    img.get_pixel(x, y).data[0]
+}
+
+#[test]
+fn answers_packets_after_the_magic_bytes() {
+    let mut printer = Printer::new(output_dir("packets"));
+
+    // nothing before the magic bytes gets an answer
+    let mut bytes = vec![0x00, 0x88, 0x00, 0x88];
+    bytes.extend_from_slice(&packet(STATUS, false, &[])[1..]);
+    assert_eq!(send(&mut printer, &bytes), (DEVICE_ID, 0x00));
+    assert_eq!(send(&mut printer, &[0x12, 0x33, 0x0f, 0x00]), (0x00, 0x00));
+
+    assert_eq!(
+        send(&mut printer, &packet(INIT, false, &[])),
+        (DEVICE_ID, 0x00)
+    );
+    // bit 3 is data waiting to be printed
+    let data = vec![0; ROW_SIZE_BYTES];
+    assert_eq!(
+        send(&mut printer, &packet(DATA, false, &data)),
+        (DEVICE_ID, 0x08)
+    );
+    assert_eq!(
+        send(&mut printer, &packet(DATA, false, &[])),
+        (DEVICE_ID, 0x08)
+    );
+
+    // then busy for a few polls
+    assert_eq!(print(&mut printer, 0, 0), 0x02);
+    let mut polls = 0;
+    while send(&mut printer, &packet(STATUS, false, &[])).1 != 0x00 {
+        polls += 1;
+    }
+    assert!(polls > 0);
+    assert_eq!(printer.pages_printed, 1);
+}
+
+#[test]
+fn reports_bad_checksums() {
+    let dir = output_dir("checksum");
+    let mut printer = Printer::new(dir.clone());
+
+    let mut bytes = packet(DATA, false, &vec![0; ROW_SIZE_BYTES]);
+    let checksum = bytes.len() - 4;
+    bytes[checksum] ^= 0xff;
+    assert_eq!(send(&mut printer, &bytes), (DEVICE_ID, 0x01));
+
+    // the data never made it in, there's nothing to print
+    // and the next good packet clears the error
+    assert_eq!(print(&mut printer, 0, 0) & 0x01, 0x00);
+    assert_eq!(printer.pages_printed, 0);
+}
+
+#[test]
+fn decompresses_data() {
+    let dir = output_dir("compressed");
+    let mut printer = Printer::new(dir.clone());
+
+    let data = [
+        0x01, 0x00, 0xff, // the first line of the first tile is color 2
+        0xff, 0xff, // then 129 times 0xff
+        0xff, 0xff, //
+        0xba, 0xff, // and 60 more, for a whole row
+    ];
+    assert_eq!(
+        send(&mut printer, &packet(DATA, true, &data)),
+        (DEVICE_ID, 0x08)
+    );
+    print(&mut printer, 0, 0);
+
+    let img = printed_page(&dir, 0);
+    assert_eq!(img.dimensions(), (160, 8));
+    assert_eq!(gray(&img, 0, 0), 85);
+    assert_eq!(gray(&img, 7, 0), 85);
+    assert_eq!(gray(&img, 0, 1), 0);
+    assert_eq!(gray(&img, 159, 7), 0);
+}
+
+#[test]
+fn maps_colors_through_the_palette() {
+    let dir = output_dir("palette");
+    let mut printer = Printer::new(dir.clone());
+
+    // color 0 everywhere
+    let data = vec![0; ROW_SIZE_BYTES];
+    for &palette in &[0x00, 0xe4, 0x1b] {
+        send(&mut printer, &packet(DATA, false, &data));
+        print(&mut printer, 0, palette);
+    }
+
+    // 0 means the default palette, where color 0 is white
+    assert_eq!(gray(&printed_page(&dir, 0), 0, 0), 255);
+    assert_eq!(gray(&printed_page(&dir, 1), 0, 0), 255);
+    assert_eq!(gray(&printed_page(&dir, 2), 0, 0), 0);
+}
+
+#[test]
+fn margins_are_left_out_of_the_image() {
+    let dir = output_dir("margins");
+    let mut printer = Printer::new(dir.clone());
+
+    let data = vec![0; ROW_SIZE_BYTES * 2];
+    send(&mut printer, &packet(DATA, false, &data));
+    // a line fed before and 3 after are just blank paper
+    print(&mut printer, 0x13, 0);
+
+    let img = printed_page(&dir, 0);
+    assert_eq!(img.dimensions(), (160, 16));
+}
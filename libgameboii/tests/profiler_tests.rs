@@ -0,0 +1,79 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::{boot_rom, run_until};
+use libgameboii::cpu::CPU;
+use libgameboii::profiler::{Profiler, Routine, RoutineStats};
+use libgameboii::symbols::Symbols;
+use std::io;
+
+const OUTER: Routine = Routine {
+    bank: 0,
+    addr: 0x000a,
+};
+const INNER: Routine = Routine {
+    bank: 0,
+    addr: 0x000e,
+};
+
+#[test]
+fn profiles_nested_calls() {
+    let code = [
+        0x31, 0xfe, 0xff, // LD SP, 0xfffe
+        0xcd, 0x0a, 0x00, // CALL Outer
+        0x18, 0xfb, // JR -5
+        0x00, 0x00, // padding
+        0xcd, 0x0e, 0x00, // Outer: CALL Inner
+        0xc9, // RET
+        0x00, // Inner: NOP
+        0xc9, // RET
+    ];
+    let boot = boot_rom(&code);
+    let cart = vec![0; 0x8000];
+
+    let mut cpu = CPU::new(&cart, &boot);
+    cpu.profiler = Some(Profiler::new());
+    let mut clock = 0;
+
+    run_until(&mut cpu, &mut clock, |cpu| cpu.PC == INNER.addr);
+    {
+        let stack = cpu.profiler.as_ref().unwrap().call_stack();
+        let calls: Vec<(Routine, u16)> = stack.iter().map(|f| (f.routine, f.call_site)).collect();
+        assert_eq!(calls, vec![(OUTER, 0x0003), (INNER, 0x000a)]);
+    }
+
+    // back in the loop after the third call. CALL counts for the caller, RET for the callee
+    run_until(&mut cpu, &mut clock, |cpu| {
+        let outer = cpu.profiler.as_ref().unwrap().stats(OUTER);
+        cpu.PC == 0x0006 && outer.map(|stats| stats.calls) == Some(3)
+    });
+
+    let profiler = cpu.profiler.as_ref().unwrap();
+    assert!(profiler.call_stack().is_empty());
+    assert_eq!(
+        profiler.stats(OUTER),
+        Some(RoutineStats {
+            calls: 3,
+            exclusive: 3 * (24 + 16),
+            inclusive: 3 * (24 + 4 + 16 + 16),
+        })
+    );
+    assert_eq!(
+        profiler.stats(INNER),
+        Some(RoutineStats {
+            calls: 3,
+            exclusive: 3 * (4 + 16),
+            inclusive: 3 * (4 + 16),
+        })
+    );
+
+    let symbols = Symbols::parse(io::Cursor::new("00:000a Outer\n00:000e Inner\n")).unwrap();
+    let mut out = vec![];
+    profiler.write_collapsed(&mut out, Some(&symbols)).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "root 108\nroot;Outer 120\nroot;Outer;Inner 60\n"
+    );
+    assert_eq!(profiler.total_cycles(), 108 + 120 + 60);
+}
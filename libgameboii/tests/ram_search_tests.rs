@@ -0,0 +1,100 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::{boot_rom, Output};
+use libgameboii::cpu::CPU;
+use libgameboii::debugger::Debugger;
+use libgameboii::ram_search::{Candidate, Filter, RamSearch};
+use libgameboii::serial::Disconnected;
+use std::io;
+
+#[test]
+fn narrows_down_candidates() {
+    let mut ram = vec![0; 0x10000];
+    let mut search = RamSearch::new(&ram);
+    //external RAM, work RAM and high RAM
+    assert_eq!(search.candidates().len(), 0x2000 + 0x2000 + 0x7f);
+
+    //video RAM and the IO registers aren't searched
+    ram[0x8000] = 1;
+    ram[0xff44] = 1;
+    ram[0xc100] = 5;
+    ram[0xa010] = 7;
+    ram[0xff90] = 3;
+    assert_eq!(search.filter(&ram, Filter::Changed), 3);
+
+    ram[0xc100] = 4;
+    ram[0xa010] = 8;
+    assert_eq!(search.filter(&ram, Filter::Decreased), 1);
+    assert_eq!(
+        search.candidates(),
+        &[Candidate {
+            addr: 0xc100,
+            value: 4,
+        }]
+    );
+
+    assert_eq!(search.filter(&ram, Filter::Equal), 1);
+    assert_eq!(search.filter(&ram, Filter::Value(3)), 0);
+
+    let mut search = RamSearch::new(&ram);
+    ram[0xff90] = 9;
+    assert_eq!(search.filter(&ram, Filter::Increased), 1);
+    assert_eq!(search.filter(&ram, Filter::Value(9)), 1);
+}
+
+#[test]
+fn searches_from_the_debugger() {
+    let code = [
+        0x21, 0x23, 0xc1, // LD HL, 0xc123
+        0x34, // INC (HL)
+        0x18, 0xfd, // JR -3
+    ];
+    let boot = boot_rom(&code);
+    let cart = vec![0; 0x8000];
+
+    let script = "\
+search list
+step
+search
+step 2
+search inc
+step 2
+search 2
+search list
+quit
+";
+    let output = Output::new();
+
+    let mut cpu = CPU::new(&cart, &boot);
+    cpu.debugger = Some(Box::new(Debugger::new(
+        Box::new(io::Cursor::new(script)),
+        Box::new(output.clone()),
+    )));
+
+    let mut clock = 0;
+    while !cpu.should_exit {
+        cpu.tick(clock, &mut None, &mut Disconnected);
+        clock += 1;
+    }
+
+    let text = output.text();
+    let replies: Vec<&str> = text
+        .lines()
+        .filter(|line| !line.contains("(gbdb) 00:"))
+        .collect();
+    assert_eq!(
+        replies,
+        vec![
+            "00:0000  LD HL 0xc123",
+            "(gbdb) no search started, use search first",
+            "(gbdb) 16511 candidates",
+            "(gbdb) 1 candidates",
+            "(gbdb) 1 candidates",
+            "(gbdb) 1 candidates",
+            "c123: 02",
+            "(gbdb) ",
+        ]
+    );
+}
@@ -0,0 +1,49 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::counter_program;
+use libgameboii::cpu::CPU;
+use libgameboii::ppu::FRAME_CLOCKS;
+use libgameboii::rewind::Rewind;
+use libgameboii::save_state::SaveState;
+use libgameboii::serial::Disconnected;
+
+#[test]
+fn rewind_goes_back_to_past_snapshots() {
+    let cart = vec![0; 0x8000];
+    let boot = counter_program();
+
+    let mut cpu = CPU::new(&cart, &boot);
+    let mut rewind = Rewind::new(1, 2);
+
+    let mut past_states = vec![];
+    for current_clock in 0..FRAME_CLOCKS * 8 + 100 {
+        rewind.tick(&cpu, current_clock);
+        if current_clock % (FRAME_CLOCKS * 2) == 0 {
+            past_states.push(SaveState::capture(&cpu, current_clock));
+        }
+
+        cpu.tick(current_clock, &mut None, &mut Disconnected);
+    }
+    assert_eq!(rewind.snapshot_count(), 5);
+
+    //from the snapshot at frame 8, 3 frames round up to 2 snapshots back
+    let state = rewind.rewind(3).unwrap();
+    assert_eq!(state.current_clock(), FRAME_CLOCKS * 4);
+
+    let mut restored_cpu = CPU::new(&cart, &boot);
+    state.restore(&mut restored_cpu).unwrap();
+
+    let mut expected_cpu = CPU::new(&cart, &boot);
+    past_states[2].restore(&mut expected_cpu).unwrap();
+
+    assert_eq!(restored_cpu.PC, expected_cpu.PC);
+    assert!(restored_cpu.RAM[..] == expected_cpu.RAM[..]);
+    assert!(*restored_cpu.ppu.screen_buffer == *expected_cpu.ppu.screen_buffer);
+
+    //it can't go further back than the first snapshot
+    let state = rewind.rewind(100).unwrap();
+    assert_eq!(state.current_clock(), 0);
+    assert_eq!(rewind.snapshot_count(), 1);
+}
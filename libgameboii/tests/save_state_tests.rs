@@ -0,0 +1,132 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::{boot_rom, counter_program, run};
+use libgameboii::cpu::CPU;
+use libgameboii::save_state::SaveState;
+use std::io;
+
+const CLOCKS: u64 = 10000;
+
+#[test]
+fn restored_state_runs_the_same() {
+    let cart = vec![0; 0x8000];
+    let boot = counter_program();
+
+    let mut cpu = CPU::new(&cart, &boot);
+    run(&mut cpu, 0..CLOCKS);
+
+    let mut file = vec![];
+    SaveState::capture(&cpu, CLOCKS)
+        .write_to(&mut file)
+        .unwrap();
+
+    run(&mut cpu, CLOCKS..CLOCKS * 2);
+
+    let state = SaveState::read_from(&mut &file[..]).unwrap();
+    let mut restored_cpu = CPU::new(&cart, &boot);
+    let clock = state.restore(&mut restored_cpu).unwrap();
+    assert_eq!(clock, CLOCKS);
+
+    run(&mut restored_cpu, clock..CLOCKS * 2);
+
+    assert_eq!(restored_cpu.PC, cpu.PC);
+    unsafe {
+        assert_eq!(restored_cpu.AF.r16, cpu.AF.r16);
+    }
+    assert!(restored_cpu.RAM[..] == cpu.RAM[..]);
+    assert!(*restored_cpu.ppu.screen_buffer == *cpu.ppu.screen_buffer);
+}
+
+#[test]
+fn state_from_another_rom_is_refused() {
+    let cart = vec![0; 0x8000];
+    let mut other_cart = cart.clone();
+    other_cart[0x150] = 0xff;
+    let boot = counter_program();
+
+    let cpu = CPU::new(&cart, &boot);
+    let state = SaveState::capture(&cpu, 0);
+
+    let mut other_cpu = CPU::new(&other_cart, &boot);
+    let error = state.restore(&mut other_cpu).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+    let error = SaveState::read_from(&mut &b"not a state"[..])
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn bad_state_changes_nothing() {
+    let cart = vec![0; 0x8000];
+    let boot = counter_program();
+
+    let mut cpu = CPU::new(&cart, &boot);
+    run(&mut cpu, 0..CLOCKS);
+
+    // a screen 4 bytes short: its length goes after the CPU, then the pixels
+    let mut file = vec![];
+    SaveState::capture(&cpu, CLOCKS)
+        .write_to(&mut file)
+        .unwrap();
+    let le_bytes = |len: u64| {
+        (0..8)
+            .map(|idx| (len >> (idx * 8)) as u8)
+            .collect::<Vec<u8>>()
+    };
+    let screen_len = 160 * 144 * 4;
+    let at = file
+        .windows(8)
+        .rposition(|bytes| bytes == &le_bytes(screen_len)[..])
+        .unwrap();
+    file[at..at + 8].copy_from_slice(&le_bytes(screen_len - 4));
+    file.drain(at + 8..at + 12);
+
+    let state = SaveState::read_from(&mut &file[..]).unwrap();
+    let mut other_cpu = CPU::new(&cart, &boot);
+    let error = state.restore(&mut other_cpu).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+    // the CPU half was fine, but it didn't go in either
+    let fresh_cpu = CPU::new(&cart, &boot);
+    assert_eq!(other_cpu.PC, fresh_cpu.PC);
+    assert!(other_cpu.RAM[..] == fresh_cpu.RAM[..]);
+}
+
+#[test]
+fn keeps_the_mbc1_banks() {
+    // MBC1+RAM+BATTERY, 8 ROM banks that start with their number and 4 RAM banks
+    let mut cart = vec![0; 8 * 0x4000];
+    for bank in 0..8 {
+        cart[bank * 0x4000] = bank as u8;
+    }
+    cart[0x147] = 0x03;
+    cart[0x149] = 0x03;
+
+    let code = [
+        0x3e, 0x0a, 0xea, 0x00, 0x00, // RAM on
+        0x3e, 0x11, 0xea, 0x00, 0xa0, // LD (0xa000), 0x11 in RAM bank 0
+        0x3e, 0x05, 0xea, 0x00, 0x20, // ROM bank 5
+        0x3e, 0x01, 0xea, 0x00, 0x60, // the upper bits pick the RAM bank
+        0x3e, 0x02, 0xea, 0x00, 0x40, // RAM bank 2
+        0x3e, 0x42, 0xea, 0x00, 0xa0, // LD (0xa000), 0x42 in RAM bank 2
+        0x18, 0xfe, // JR -2
+    ];
+    let boot = boot_rom(&code);
+
+    let mut cpu = CPU::new(&cart, &boot);
+    run(&mut cpu, 0..1000);
+    assert_eq!(cpu.rom_bank(0x4000), 5);
+    assert_eq!(cpu.RAM[0x4000], 5);
+    assert_eq!(cpu.RAM[0xa000], 0x42);
+
+    let state = SaveState::capture(&cpu, 1000);
+    let mut restored_cpu = CPU::new(&cart, &boot);
+    state.restore(&mut restored_cpu).unwrap();
+    assert_eq!(restored_cpu.rom_bank(0x4000), 5);
+    assert_eq!(restored_cpu.rom_offset(0x4000), Some(5 * 0x4000));
+    assert!(restored_cpu.RAM[..] == cpu.RAM[..]);
+}
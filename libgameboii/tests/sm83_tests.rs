@@ -0,0 +1,222 @@
+extern crate libgameboii;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+
+use libgameboii::cpu::CPU;
+use libgameboii::interpreter;
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use std::panic;
+use std::path::Path;
+
+// one file per opcode from https://github.com/SingleStepTests/sm83, eg. "00.json" or "cb 37.json"
+const VECTORS_DIR: &str = "tests/sm83/v1";
+// a few vectors in the same format, one or two for each ALU family
+const ALU_VECTORS: &str = "tests/sm83_vectors.json";
+
+#[derive(Deserialize)]
+struct State {
+    pc: u16,
+    sp: u16,
+    a: u8,
+    b: u8,
+    c: u8,
+    d: u8,
+    e: u8,
+    f: u8,
+    h: u8,
+    l: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+#[derive(Deserialize)]
+struct Vector {
+    name: String,
+    initial: State,
+    #[serde(rename = "final")]
+    expected: State,
+    // one entry per M-cycle, only the count is checked
+    cycles: Vec<serde_json::Value>,
+}
+
+fn registers(cpu: &CPU) -> [(&'static str, u16); 10] {
+    unsafe {
+        [
+            ("PC", cpu.PC),
+            ("SP", cpu.SP),
+            ("A", cpu.AF.r8.first as u16),
+            ("F", cpu.AF.r8.second as u16),
+            ("B", cpu.BC.r8.first as u16),
+            ("C", cpu.BC.r8.second as u16),
+            ("D", cpu.DE.r8.first as u16),
+            ("E", cpu.DE.r8.second as u16),
+            ("H", cpu.HL.r8.first as u16),
+            ("L", cpu.HL.r8.second as u16),
+        ]
+    }
+}
+
+fn expected_registers(state: &State) -> [(&'static str, u16); 10] {
+    [
+        ("PC", state.pc),
+        ("SP", state.sp),
+        ("A", state.a as u16),
+        ("F", state.f as u16),
+        ("B", state.b as u16),
+        ("C", state.c as u16),
+        ("D", state.d as u16),
+        ("E", state.e as u16),
+        ("H", state.h as u16),
+        ("L", state.l as u16),
+    ]
+}
+
+// runs the instruction at PC and lists what doesn't match
+fn run_vector(vector: &Vector) -> Vec<String> {
+    let mut cpu = CPU::new_flat_ram();
+    let initial = &vector.initial;
+    cpu.PC = initial.pc;
+    cpu.SP = initial.sp;
+    cpu.AF.r16 = (initial.a as u16) << 8 | initial.f as u16;
+    cpu.BC.r16 = (initial.b as u16) << 8 | initial.c as u16;
+    cpu.DE.r16 = (initial.d as u16) << 8 | initial.e as u16;
+    cpu.HL.r16 = (initial.h as u16) << 8 | initial.l as u16;
+    for &(addr, val) in &initial.ram {
+        cpu.RAM[addr as usize] = val;
+    }
+
+    // the prefix is taken off the same way CPU::tick does
+    let mut instr = cpu.RAM[cpu.PC as usize] as u16;
+    if instr == 0xcb {
+        cpu.PC = cpu.PC.wrapping_add(1);
+        instr = 0xcb00 | cpu.RAM[cpu.PC as usize] as u16;
+    }
+
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        unsafe {
+            interpreter::interpret(instr, &mut cpu);
+        }
+        cpu
+    }));
+    let cpu = match result {
+        Ok(cpu) => cpu,
+        Err(_) => return vec![String::from("the emulator panicked")],
+    };
+
+    let mut errors = vec![];
+    let actual = registers(&cpu);
+    for (&(name, actual), &(_, expected)) in actual
+        .iter()
+        .zip(expected_registers(&vector.expected).iter())
+    {
+        if actual != expected {
+            errors.push(format!("{} is {:x}, expected {:x}", name, actual, expected));
+        }
+    }
+
+    for &(addr, expected) in &vector.expected.ram {
+        let actual = cpu.RAM[addr as usize];
+        if actual != expected {
+            errors.push(format!(
+                "({:04x}) is {:02x}, expected {:02x}",
+                addr, actual, expected
+            ));
+        }
+    }
+
+    let cycles = vector.cycles.len() as u64 * 4;
+    if cpu.next_clock() != cycles {
+        errors.push(format!(
+            "took {} cycles, expected {}",
+            cpu.next_clock(),
+            cycles
+        ));
+    }
+
+    errors
+}
+
+fn read_vectors(path: &Path) -> Vec<Vector> {
+    serde_json::from_reader(BufReader::new(File::open(path).unwrap())).unwrap()
+}
+
+fn run_vectors(vectors: &[Vector]) -> Vec<String> {
+    vectors
+        .iter()
+        .filter_map(|vector| {
+            let errors = run_vector(vector);
+            if errors.is_empty() {
+                None
+            } else {
+                Some(format!("{}: {}", vector.name, errors.join(", ")))
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn runs_vectors() {
+    let vectors = read_vectors(Path::new(ALU_VECTORS));
+    let failures = run_vectors(&vectors);
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+
+    // and notices when something is off
+    let mut vectors = vectors;
+    vectors[0].expected.f = 0;
+    vectors[1].cycles.pop();
+    assert_eq!(
+        run_vectors(&vectors),
+        vec![
+            "f8 carry out of the low byte: F is 30, expected 0",
+            "cb 36 through memory: took 16 cycles, expected 12",
+        ]
+    );
+}
+
+// needs the vectors in tests/sm83/v1
+#[test]
+#[ignore]
+fn sm83() {
+    let mut paths: Vec<_> = fs::read_dir(VECTORS_DIR)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map(|ext| ext == "json").unwrap_or(false))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no vectors in {}", VECTORS_DIR);
+
+    // the panics are counted as failures, don't print each of them
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut failed_opcodes = vec![];
+    for path in &paths {
+        let vectors = read_vectors(path);
+        let failures = run_vectors(&vectors);
+        if !failures.is_empty() {
+            // the first one is enough to start from
+            println!(
+                "{} failed {} of {}: {}",
+                opcode_name(path),
+                failures.len(),
+                vectors.len(),
+                failures[0]
+            );
+            failed_opcodes.push(opcode_name(path));
+        }
+    }
+
+    let _ = panic::take_hook();
+    assert!(
+        failed_opcodes.is_empty(),
+        "{} of {} opcodes failed: {}",
+        failed_opcodes.len(),
+        paths.len(),
+        failed_opcodes.join(", ")
+    );
+}
+
+fn opcode_name(path: &Path) -> String {
+    path.file_stem().unwrap().to_string_lossy().into_owned()
+}
@@ -0,0 +1,135 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::{boot_rom, run};
+use libgameboii::cpu::CPU;
+use libgameboii::ppu::{Shades, FRAME_CLOCKS, PPU};
+
+const WHITE: u8 = 0xff;
+const BLACK: u8 = 0x00;
+
+// LCD on, tiles from 0x8000, background on
+const LCDC: u8 = 0x91;
+const OBJ_ON: u8 = 0x02;
+const TALL_OBJ: u8 = 0x04;
+
+// tiles 1 and 3 are all black, the background is tile 0. bg goes in the first byte of
+// tile 0, so it shows on every 8th line. The only sprite on screen is the first one in
+// OAM, OBP0 is the same as BGP and OBP1 is the other way around
+fn sprite_program(sprite: [u8; 4], lcdc: u8, bg: u8) -> Vec<u8> {
+    let code = [
+        0x21, 0x10, 0x80, // LD HL, 0x8010: tile 1
+        0x3e, 0xff, // LD A, 0xff
+        0x06, 0x10, // LD B, 16
+        0x22, // LD (HL+), A
+        0x05, // DEC B
+        0x20, 0xfc, // JR NZ, -4
+        0x21, 0x30, 0x80, // LD HL, 0x8030: tile 3
+        0x06, 0x10, // LD B, 16
+        0x22, // LD (HL+), A
+        0x05, // DEC B
+        0x20, 0xfc, // JR NZ, -4
+        0x3e, bg, // LD A, bg
+        0xea, 0x00, 0x80, // LD (0x8000), A
+        0x21, 0x00, 0xfe, // LD HL, 0xfe00: OAM
+        0x3e, sprite[0], // LD A, y
+        0x22,      // LD (HL+), A
+        0x3e, sprite[1], // LD A, x
+        0x22,      // LD (HL+), A
+        0x3e, sprite[2], // LD A, tile
+        0x22,      // LD (HL+), A
+        0x3e, sprite[3], // LD A, attributes
+        0x22,      // LD (HL+), A
+        0x3e, 0xe4, // LD A, 0xe4
+        0xe0, 0x47, // LDH (0x47), A: BGP
+        0xe0, 0x48, // LDH (0x48), A: OBP0
+        0x3e, 0x1b, // LD A, 0x1b
+        0xe0, 0x49, // LDH (0x49), A: OBP1
+        0x3e, lcdc, // LD A, lcdc
+        0xe0, 0x40, // LDH (0x40), A
+        0x18, 0xfe, // JR -2
+    ];
+
+    boot_rom(&code)
+}
+
+fn render(boot: &[u8]) -> PPU {
+    let cart = vec![0; 0x8000];
+    let mut cpu = CPU::new(&cart, boot);
+    cpu.ppu.shades = Shades::Grayscale;
+
+    run(&mut cpu, 0..3 * FRAME_CLOCKS);
+    cpu.ppu
+}
+
+fn level(ppu: &PPU, x: u32, y: u32) -> u8 {
+    ppu.screen_buffer.get_pixel(x, y)[0]
+}
+
+#[test]
+fn draws_sprites() {
+    // OAM positions count from (-8, -16), this one is at (16, 8)
+    let ppu = render(&sprite_program([8 + 16, 16 + 8, 1, 0], LCDC | OBJ_ON, 0));
+
+    assert_eq!(level(&ppu, 16, 8), BLACK);
+    assert_eq!(level(&ppu, 23, 15), BLACK);
+    assert_eq!(level(&ppu, 15, 8), WHITE);
+    assert_eq!(level(&ppu, 24, 8), WHITE);
+    assert_eq!(level(&ppu, 16, 7), WHITE);
+    assert_eq!(level(&ppu, 16, 16), WHITE);
+}
+
+#[test]
+fn sprites_off() {
+    let ppu = render(&sprite_program([8 + 16, 16 + 8, 1, 0], LCDC, 0));
+    assert_eq!(level(&ppu, 16, 8), WHITE);
+}
+
+#[test]
+fn obp1() {
+    let ppu = render(&sprite_program([8 + 16, 16 + 8, 1, 0x10], LCDC | OBJ_ON, 0));
+    assert_eq!(level(&ppu, 16, 8), WHITE);
+}
+
+#[test]
+fn tall_sprites() {
+    // the lowest bit of the tile is ignored, so the top half is the blank tile 2
+    let ppu = render(&sprite_program(
+        [8 + 16, 16 + 8, 3, 0],
+        LCDC | OBJ_ON | TALL_OBJ,
+        0,
+    ));
+    assert_eq!(level(&ppu, 16, 8), WHITE);
+    assert_eq!(level(&ppu, 16, 15), WHITE);
+    assert_eq!(level(&ppu, 16, 16), BLACK);
+    assert_eq!(level(&ppu, 16, 23), BLACK);
+    assert_eq!(level(&ppu, 16, 24), WHITE);
+
+    // flipped, the halves swap
+    let ppu = render(&sprite_program(
+        [8 + 16, 16 + 8, 3, 0x40],
+        LCDC | OBJ_ON | TALL_OBJ,
+        0,
+    ));
+    assert_eq!(level(&ppu, 16, 8), BLACK);
+    assert_eq!(level(&ppu, 16, 15), BLACK);
+    assert_eq!(level(&ppu, 16, 16), WHITE);
+}
+
+#[test]
+fn sprites_behind_the_background() {
+    let ppu = render(&sprite_program([8 + 16, 16 + 8, 1, 0], LCDC | OBJ_ON, 0xff));
+    assert_eq!(level(&ppu, 16, 8), BLACK);
+
+    // only background color 0 lets it through
+    let ppu = render(&sprite_program(
+        [8 + 16, 16 + 8, 1, 0x80],
+        LCDC | OBJ_ON,
+        0xff,
+    ));
+    let background = level(&ppu, 0, 8);
+    assert!(background != WHITE && background != BLACK);
+    assert_eq!(level(&ppu, 16, 8), background);
+    assert_eq!(level(&ppu, 16, 9), BLACK);
+}
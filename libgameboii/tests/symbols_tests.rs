@@ -0,0 +1,96 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::{boot_rom, Output};
+use libgameboii::cpu::CPU;
+use libgameboii::debugger::Debugger;
+use libgameboii::disasm;
+use libgameboii::serial::Disconnected;
+use libgameboii::symbols::Symbols;
+use std::io;
+
+const SYMBOLS: &str = "\
+; File generated by rgblink
+00:0000 Start
+00:0003 Start.call
+00:0008 Func
+01:4000 Banked
+00:C000 wCounter
+";
+
+#[test]
+fn parses_sym_files() {
+    let symbols = Symbols::parse(io::Cursor::new(SYMBOLS)).unwrap();
+
+    assert_eq!(symbols.len(), 5);
+    assert_eq!(symbols.lookup("Banked"), Some((1, 0x4000)));
+    assert_eq!(symbols.label(1, 0x4000), Some("Banked"));
+    assert_eq!(symbols.label(2, 0x4000), None);
+    assert_eq!(symbols.describe(0, 0x0009), Some("Func+1".to_owned()));
+    assert_eq!(symbols.describe(1, 0x4010), Some("Banked+16".to_owned()));
+    // RAM labels don't cover the ROM below them
+    assert_eq!(symbols.describe(0, 0xc003), Some("wCounter+3".to_owned()));
+    assert_eq!(symbols.describe(0, 0x8000), None);
+
+    assert!(Symbols::parse(io::Cursor::new("00:zz Nope")).is_err());
+
+    // labels in the disassembly, and as jump targets
+    let code = [0x18, 0xfe, 0xc3, 0x00, 0x40];
+    let instructions = disasm::disassemble(&code, 1, 0x4000);
+    let mut out = vec![];
+    disasm::write_rgbds(&mut out, &instructions, Some(&symbols)).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "SECTION \"ROM Bank $01\", ROMX[$4000], BANK[$01]\n\n\
+         Banked:\n\
+         \tjr Banked               ; $4000\n\
+         \tjp Banked               ; $4002\n"
+    );
+}
+
+#[test]
+fn debugger_uses_labels() {
+    let code = [
+        0x31, 0xfe, 0xff, // LD SP, 0xfffe
+        0xcd, 0x08, 0x00, // CALL Func
+        0x18, 0xfe, // JR -2
+        0x3c, // Func: INC A
+        0xc9, // RET
+    ];
+    let boot = boot_rom(&code);
+    let cart = vec![0; 0x8000];
+
+    let script = "\
+break Func
+continue
+backtrace
+quit
+";
+    let output = Output::new();
+    let mut debugger = Debugger::new(Box::new(io::Cursor::new(script)), Box::new(output.clone()));
+    debugger.set_symbols(Symbols::parse(io::Cursor::new(SYMBOLS)).unwrap());
+
+    let mut cpu = CPU::new(&cart, &boot);
+    cpu.debugger = Some(Box::new(debugger));
+
+    let mut clock = 0;
+    while !cpu.should_exit {
+        cpu.tick(clock, &mut None, &mut Disconnected);
+        clock += 1;
+    }
+
+    let text = output.text();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "00:0000 <Start>  LD SP 0xfffe",
+            "(gbdb) breakpoint 0",
+            "(gbdb) 00:0008 <Func>  INC A",
+            "(gbdb) #0  00:0008 <Func>",
+            "#1  00:0003 <Start.call>",
+            "(gbdb) ",
+        ]
+    );
+}
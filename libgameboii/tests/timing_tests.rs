@@ -0,0 +1,134 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::{boot_rom, run};
+use libgameboii::cpu::CPU;
+use libgameboii::interpreter;
+
+const NOP: u8 = 0x00;
+
+#[test]
+fn reads_happen_on_their_own_cycle() {
+    // both reads start 256 clocks after DIV is reset, when it's about to go up.
+    // LD A,(HL) reads on its second cycle, still before that, LD A,(a16) on its fourth
+    let mut code = vec![
+        0x21, 0x04, 0xff, // LD HL, 0xff04
+        0xe0, 0x04, // LDH (0x04), A: DIV is reset on the last cycle
+    ];
+    code.extend_from_slice(&[NOP; 61]);
+    code.extend_from_slice(&[
+        0x7e, // LD A, (HL)
+        0x47, // LD B, A
+        0xe0, 0x04, // LDH (0x04), A
+    ]);
+    code.extend_from_slice(&[NOP; 61]);
+    code.extend_from_slice(&[
+        0xfa, 0x04, 0xff, // LD A, (0xff04)
+        0x4f, // LD C, A
+        0x18, 0xfe, // JR -2
+    ]);
+
+    let cart = vec![0; 0x8000];
+    let mut cpu = CPU::new(&cart, &boot_rom(&code));
+    run(&mut cpu, 0..1000);
+    unsafe {
+        assert_eq!(cpu.BC.r8.first, 0);
+        assert_eq!(cpu.BC.r8.second, 1);
+    }
+}
+
+// LY, read by an instruction started the same number of NOPs after the LCD is turned on
+fn ly_after_lcd_on(nops: usize, read: &[u8]) -> u8 {
+    let mut code = vec![
+        0x21, 0x44, 0xff, // LD HL, 0xff44
+        0x3e, 0x91, // LD A, 0x91
+        0xe0, 0x40, // LDH (0x40), A: LCD on
+    ];
+    code.extend_from_slice(&vec![NOP; nops]);
+    code.extend_from_slice(read);
+    code.extend_from_slice(&[
+        0x47, // LD B, A
+        0x18, 0xfe, // JR -2
+    ]);
+
+    let cart = vec![0; 0x8000];
+    let mut cpu = CPU::new(&cart, &boot_rom(&code));
+    run(&mut cpu, 0..2000);
+    unsafe { cpu.BC.r8.first }
+}
+
+#[test]
+fn ppu_runs_between_reads() {
+    // the first line is over right after the second cycle of the read
+    assert_eq!(ly_after_lcd_on(109, &[0x7e]), 0); // LD A, (HL)
+    assert_eq!(ly_after_lcd_on(109, &[0xfa, 0x44, 0xff]), 1); // LD A, (0xff44)
+}
+
+#[test]
+fn timers_keep_their_rate() {
+    let code = [
+        0x3e, 0x05, // LD A, 5
+        0xe0, 0x07, // LDH (0x07), A: TIMA goes up every 16 clocks
+        0x18, 0xfe, // JR -2
+    ];
+
+    let cart = vec![0; 0x8000];
+    let mut cpu = CPU::new(&cart, &boot_rom(&code));
+    // DIV goes up every 256 clocks, TIMA started 32 clocks in
+    run(&mut cpu, 0..256 * 10);
+    assert_eq!(cpu.RAM[0xff04], 10);
+    assert_eq!(cpu.RAM[0xff05] as u64, (256 * 10 - 32) / 16);
+}
+
+#[test]
+fn branches_take_longer_when_taken() {
+    // opcode, cycles taken, cycles not taken. All of them branch on Z
+    let branches = [
+        (0x28, 12, 8),  // JR Z, r8
+        (0xca, 16, 12), // JP Z, a16
+        (0xcc, 24, 12), // CALL Z, a16
+        (0xc8, 20, 8),  // RET Z
+    ];
+
+    for &(opcode, taken, not_taken) in &branches {
+        for &(z, cycles) in &[(true, taken), (false, not_taken)] {
+            let mut cpu = CPU::new_flat_ram();
+            cpu.PC = 0x0100;
+            cpu.SP = 0xfffe;
+            cpu.RAM[0x0100] = opcode;
+            unsafe {
+                cpu.set_z(z);
+                interpreter::interpret(opcode as u16, &mut cpu);
+            }
+            assert_eq!(cpu.next_clock(), cycles, "{:02x} with Z={}", opcode, z);
+        }
+    }
+
+    let disasm = libgameboii::disasm::opcode(0x20).unwrap();
+    assert_eq!((disasm.cycles, disasm.cycles_not_taken), (12, Some(8)));
+}
+
+#[test]
+fn bit_on_hl_doesnt_write_back() {
+    // BIT only reads (HL), the rest of the CB ops on it read and write
+    let ops = [
+        (0xcb46, 12), // BIT 0, (HL)
+        (0xcb7e, 12), // BIT 7, (HL)
+        (0xcb86, 16), // RES 0, (HL)
+        (0xcbc6, 16), // SET 0, (HL)
+        (0xcb06, 16), // RLC (HL)
+    ];
+
+    for &(opcode, cycles) in &ops {
+        let mut cpu = CPU::new_flat_ram();
+        cpu.PC = 0x0101;
+        cpu.RAM[0x0100] = 0xcb;
+        cpu.RAM[0x0101] = opcode as u8;
+        cpu.HL.r16 = 0xc000;
+        unsafe {
+            interpreter::interpret(opcode, &mut cpu);
+        }
+        assert_eq!(cpu.next_clock(), cycles, "{:04x}", opcode);
+    }
+}
@@ -0,0 +1,41 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::{hand_over_boot_rom, Output};
+use libgameboii::cpu::CPU;
+use libgameboii::debug_log::Log;
+use libgameboii::serial::Disconnected;
+
+#[test]
+fn traces_in_gameboy_doctor_format() {
+    let boot = hand_over_boot_rom();
+    let mut cart = vec![0; 0x8000];
+    cart[4..9].copy_from_slice(&[
+        0x06, 0x12, // LD B, 0x12
+        0x04, // INC B
+        0x18, 0xfd, // JR -3
+    ]);
+
+    let output = Output::new();
+    let mut log = Some(Log::trace(Box::new(output.clone())));
+
+    let mut cpu = CPU::new(&cart, &boot);
+    for clock in 0..200 {
+        cpu.tick(clock, &mut log, &mut Disconnected);
+    }
+
+    let trace = output.text();
+    let lines: Vec<&str> = trace.lines().collect();
+
+    // nothing from the boot ROM
+    assert_eq!(
+        lines[..4].to_vec(),
+        vec![
+            "A:01 F:00 B:00 C:00 D:00 E:00 H:00 L:00 SP:0000 PC:0004 PCMEM:06,12,04,18",
+            "A:01 F:00 B:12 C:00 D:00 E:00 H:00 L:00 SP:0000 PC:0006 PCMEM:04,18,FD,00",
+            "A:01 F:00 B:13 C:00 D:00 E:00 H:00 L:00 SP:0000 PC:0007 PCMEM:18,FD,00,00",
+            "A:01 F:00 B:13 C:00 D:00 E:00 H:00 L:00 SP:0000 PC:0006 PCMEM:04,18,FD,00",
+        ]
+    );
+}
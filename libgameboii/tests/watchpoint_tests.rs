@@ -0,0 +1,62 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::counter_program;
+use libgameboii::cpu::CPU;
+use libgameboii::serial::Disconnected;
+use libgameboii::watchpoint::{Access, WatchCondition, WatchHit};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const COUNTER_ADDR: usize = 0xc000;
+
+#[test]
+fn watchpoints_report_accesses_and_pause() {
+    let cart = vec![0; 0x8000];
+    let boot = counter_program();
+    let mut cpu = CPU::new(&cart, &boot);
+
+    let hits = Rc::new(RefCell::new(Vec::<WatchHit>::new()));
+    {
+        let hits = hits.clone();
+        let mut watchpoints = cpu.watchpoints.borrow_mut();
+        watchpoints.add(COUNTER_ADDR..COUNTER_ADDR + 1, WatchCondition::Change);
+        watchpoints.add(COUNTER_ADDR..COUNTER_ADDR + 1, WatchCondition::Read);
+        watchpoints.set_callback(Box::new(move |hit| {
+            hits.borrow_mut().push(*hit);
+            //stop when the counter gets to 3
+            hit.new == 3
+        }));
+    }
+
+    let mut current_clock = 0;
+    while !cpu.watchpoints.borrow_mut().take_pause() {
+        cpu.tick(current_clock, &mut None, &mut Disconnected);
+        current_clock += 1;
+    }
+
+    let hits = hits.borrow();
+    assert_eq!(hits.len(), 6);
+    assert_eq!(
+        hits[0],
+        WatchHit {
+            pc: 0x0003,
+            addr: COUNTER_ADDR as u16,
+            old: 0,
+            new: 0,
+            access: Access::Read,
+        }
+    );
+    assert_eq!(
+        hits[5],
+        WatchHit {
+            pc: 0x0003,
+            addr: COUNTER_ADDR as u16,
+            old: 2,
+            new: 3,
+            access: Access::Write,
+        }
+    );
+    assert_eq!(cpu.RAM[COUNTER_ADDR], 3);
+}
@@ -0,0 +1,78 @@
+extern crate libgameboii;
+
+mod common;
+
+use common::{boot_rom, run};
+use libgameboii::cpu::CPU;
+use libgameboii::ppu::{Shades, FRAME_CLOCKS, PPU};
+
+const WHITE: u8 = 0xff;
+const BLACK: u8 = 0x00;
+
+// the background is tile 0, all white. The top row of the window is tile 1, all black,
+// the rest of it is white too
+fn window_program(wy: u8, wx: u8) -> Vec<u8> {
+    let code = [
+        0x21, 0x10, 0x80, // LD HL, 0x8010: tile 1
+        0x3e, 0xff, // LD A, 0xff
+        0x06, 0x10, // LD B, 16
+        0x22, // LD (HL+), A
+        0x05, // DEC B
+        0x20, 0xfc, // JR NZ, -4
+        0x21, 0x00, 0x9c, // LD HL, 0x9c00: the first row of the window map
+        0x3e, 0x01, // LD A, 1
+        0x06, 0x20, // LD B, 32
+        0x22, // LD (HL+), A
+        0x05, // DEC B
+        0x20, 0xfc, // JR NZ, -4
+        0x3e, wy, // LD A, wy
+        0xe0, 0x4a, // LDH (0x4a), A: WY
+        0x3e, wx, // LD A, wx
+        0xe0, 0x4b, // LDH (0x4b), A: WX
+        0x3e, 0xe4, // LD A, 0xe4
+        0xe0, 0x47, // LDH (0x47), A: BGP
+        0x3e, 0xf1, // LD A, 0xf1
+        0xe0, 0x40, // LDH (0x40), A: LCD, window from 0x9c00, tiles from 0x8000, background
+        0x18, 0xfe, // JR -2
+    ];
+
+    boot_rom(&code)
+}
+
+fn render(boot: &[u8]) -> PPU {
+    let cart = vec![0; 0x8000];
+    let mut cpu = CPU::new(&cart, boot);
+    cpu.ppu.shades = Shades::Grayscale;
+
+    run(&mut cpu, 0..3 * FRAME_CLOCKS);
+    cpu.ppu
+}
+
+fn level(ppu: &PPU, x: u32, y: u32) -> u8 {
+    ppu.screen_buffer.get_pixel(x, y)[0]
+}
+
+#[test]
+fn draws_the_window() {
+    // the window starts at (80, 40)
+    let ppu = render(&window_program(40, 80 + 7));
+
+    assert_eq!(level(&ppu, 80, 39), WHITE);
+    assert_eq!(level(&ppu, 79, 40), WHITE);
+    assert_eq!(level(&ppu, 80, 40), BLACK);
+    assert_eq!(level(&ppu, 159, 47), BLACK);
+    // the window counts its own lines, from WY
+    assert_eq!(level(&ppu, 80, 48), WHITE);
+    assert_eq!(level(&ppu, 0, 0), WHITE);
+}
+
+#[test]
+fn wx_counts_from_7() {
+    let ppu = render(&window_program(0, 7));
+    assert_eq!(level(&ppu, 0, 0), BLACK);
+    assert_eq!(level(&ppu, 0, 8), WHITE);
+
+    // off the right edge
+    let ppu = render(&window_program(0, 167));
+    assert_eq!(level(&ppu, 159, 0), WHITE);
+}